@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE
+  user_sessions (
+    session_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL
+  );
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Publishing now requires an authenticated user, anonymous (nil user id) entries are obsolete.
+DELETE FROM idempotency
+WHERE
+  user_id NOT IN (
+    SELECT
+      user_id
+    FROM
+      users
+  );
+
+ALTER TABLE idempotency
+ADD CONSTRAINT idempotency_user_id_fkey FOREIGN KEY (user_id) REFERENCES users (user_id);
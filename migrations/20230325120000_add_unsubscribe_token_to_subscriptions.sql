@@ -0,0 +1,6 @@
+-- Every subscriber gets an opaque token embedded in the unsubscribe links of the emails we send.
+ALTER TABLE subscriptions ADD COLUMN unsubscribe_token TEXT NULL;
+UPDATE subscriptions
+SET unsubscribe_token = replace(gen_random_uuid()::text, '-', '');
+ALTER TABLE subscriptions ALTER COLUMN unsubscribe_token SET NOT NULL;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_unsubscribe_token_key UNIQUE (unsubscribe_token);
@@ -0,0 +1,7 @@
+-- Confirmation tokens are only valid for a limited time after they have been issued.
+ALTER TABLE subscriptions_tokens
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '24 hours';
+ALTER TABLE subscriptions_tokens
+ALTER COLUMN created_at DROP DEFAULT,
+ALTER COLUMN expires_at DROP DEFAULT;
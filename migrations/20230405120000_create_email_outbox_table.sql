@@ -0,0 +1,14 @@
+-- Transactional emails are written here in the same transaction as the changes that trigger
+-- them, and sent out by a background dispatcher.
+CREATE TABLE
+  email_outbox (
+    email_id uuid NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (email_id)
+  );
@@ -0,0 +1,25 @@
+-- Data export and erasure requests, verified through a link emailed to the subscriber.
+CREATE TABLE
+  subscriber_data_requests (
+    request_token TEXT NOT NULL,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('export', 'erasure')),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (request_token)
+  );
+
+-- One row per erased subscriber. It records that an erasure happened and what it removed,
+-- nothing that could identify the subscriber.
+CREATE TABLE
+  subscriber_erasures (
+    erasure_id uuid NOT NULL,
+    erased_at timestamptz NOT NULL,
+    requested_by TEXT NOT NULL CHECK (requested_by IN ('subscriber', 'admin')),
+    subscription_status TEXT NOT NULL,
+    n_tokens BIGINT NOT NULL,
+    n_pending_deliveries BIGINT NOT NULL,
+    n_failed_deliveries BIGINT NOT NULL,
+    n_pending_emails BIGINT NOT NULL,
+    PRIMARY KEY (erasure_id)
+  );
@@ -0,0 +1,6 @@
+-- Every admin has a role. The seeded admin, the only user so far, becomes the owner.
+ALTER TABLE users
+ADD COLUMN role TEXT NOT NULL DEFAULT 'owner' CHECK (role IN ('owner', 'editor', 'viewer')),
+ADD COLUMN email TEXT UNIQUE;
+ALTER TABLE users
+ALTER COLUMN role DROP DEFAULT;
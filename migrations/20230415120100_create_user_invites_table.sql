@@ -0,0 +1,9 @@
+-- Pending invitations to become an admin, at most one per email.
+CREATE TABLE user_invites (
+    invite_token TEXT PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    invited_by uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
@@ -0,0 +1,13 @@
+-- TOTP secrets are encrypted with the application's encryption key. A secret is pending until the
+-- user has proven they can generate codes with it.
+ALTER TABLE users
+ADD COLUMN totp_secret BYTEA,
+ADD COLUMN totp_pending_secret BYTEA,
+ADD COLUMN totp_last_used_step BIGINT;
+
+-- Single-use codes to log in without the authenticator app, stored hashed.
+CREATE TABLE totp_recovery_codes (
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
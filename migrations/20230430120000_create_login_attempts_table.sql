@@ -0,0 +1,8 @@
+-- Failed logins, keyed by `username:<username>` or `ip:<address>`. Rows older than the
+-- throttling window are pruned as new failures come in.
+CREATE TABLE login_attempts (
+    throttle_key TEXT NOT NULL,
+    attempted_at timestamptz NOT NULL
+);
+CREATE INDEX login_attempts_throttle_key_idx ON login_attempts (throttle_key, attempted_at);
+CREATE INDEX login_attempts_attempted_at_idx ON login_attempts (attempted_at);
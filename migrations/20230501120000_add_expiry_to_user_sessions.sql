@@ -0,0 +1,5 @@
+-- Session state expires in the session store on its own: the matching rows are pruned by the
+-- cleanup worker once they are past `expires_at`.
+ALTER TABLE user_sessions ADD COLUMN expires_at timestamptz;
+UPDATE user_sessions SET expires_at = created_at + interval '1 day';
+ALTER TABLE user_sessions ALTER COLUMN expires_at SET NOT NULL;
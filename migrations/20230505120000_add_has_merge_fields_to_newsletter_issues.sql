@@ -0,0 +1,3 @@
+-- Issues published before merge fields existed may contain `{{` as plain text: they are
+-- delivered as-is, only issues validated at publish time have their merge fields filled in.
+ALTER TABLE newsletter_issues ADD COLUMN has_merge_fields BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- Wrong codes in a row at the second step of the login, across all of the user's pending logins.
+ALTER TABLE users ADD COLUMN totp_failed_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,90 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use anyhow::Context;
+use base64::Engine;
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+
+const NONCE_LENGTH: usize = 12;
+
+/// Encrypts the secrets we have to store in the database but must be able to read back, unlike
+/// passwords. Each ciphertext carries its own random nonce.
+#[derive(Clone)]
+pub struct EncryptionKey(Aes256Gcm);
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey([REDACTED])")
+    }
+}
+
+impl EncryptionKey {
+    /// Parses 32 base64-encoded bytes, the format of `application.encryption_key`.
+    pub fn parse(key: &Secret<String>) -> Result<Self, anyhow::Error> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(key.expose_secret())
+            .context("The encryption key is not valid base64.")?;
+        let cipher = Aes256Gcm::new_from_slice(&bytes)
+            .map_err(|_| anyhow::anyhow!("The encryption key must be 32 bytes long."))?;
+        Ok(Self(cipher))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .0
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt a secret."))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        if data.len() < NONCE_LENGTH {
+            anyhow::bail!("The encrypted secret is too short to contain a nonce.");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        self.0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt a secret, was the key changed?"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EncryptionKey;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn key(base64: &str) -> EncryptionKey {
+        EncryptionKey::parse(&Secret::new(base64.to_string())).unwrap()
+    }
+
+    #[test]
+    fn secrets_round_trip_and_ciphertexts_differ() {
+        let key = key("P2zA0u1IqzFCZGAOogdS9hRDjNOruqORwnqMsc39Dzs=");
+        let first = key.encrypt(b"a secret").unwrap();
+        let second = key.encrypt(b"a secret").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(key.decrypt(&first).unwrap(), b"a secret");
+    }
+
+    #[test]
+    fn secrets_cannot_be_decrypted_with_another_key() {
+        let ciphertext = key("P2zA0u1IqzFCZGAOogdS9hRDjNOruqORwnqMsc39Dzs=")
+            .encrypt(b"a secret")
+            .unwrap();
+        let other = key("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+        assert_err!(other.decrypt(&ciphertext));
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes_long() {
+        assert_err!(EncryptionKey::parse(&Secret::new("c2hvcnQ=".into())));
+        assert_ok!(EncryptionKey::parse(&Secret::new(
+            "P2zA0u1IqzFCZGAOogdS9hRDjNOruqORwnqMsc39Dzs=".into()
+        )));
+    }
+}
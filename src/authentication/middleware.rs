@@ -0,0 +1,125 @@
+use std::ops::Deref;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    web, FromRequest, HttpMessage,
+};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::middleware::Next;
+use sqlx::PgPool;
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{get_role, is_session_active, Role},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+/// The id of the logged-in user, available to every handler mounted behind
+/// [`reject_anonymous_users`] via `web::ReqData<UserId>`. Their [`Role`] is available the same
+/// way, looked up on every request so that a role change takes effect immediately.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// Rejections are returned as redirect responses rather than `Err`s: an error coming out of a
+// middleware would bypass the flash message middleware and the message would be lost.
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error("You must be logged in to access this page.").send();
+            return Ok(req.into_response(see_other("/login")).map_into_right_body());
+        }
+    };
+
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .cloned()
+        .ok_or_else(|| e500("The database pool is missing from the application state."))?;
+    let is_active = match session.get_session_id().map_err(e500)? {
+        Some(session_id) => is_session_active(user_id, session_id, &pool)
+            .await
+            .map_err(e500)?,
+        None => false,
+    };
+    if !is_active {
+        session.log_out();
+        FlashMessage::error("Your session has ended, please log in again.").send();
+        return Ok(req.into_response(see_other("/login")).map_into_right_body());
+    }
+
+    if let Some(root_span) = req.extensions().get::<RootSpan>() {
+        root_span.record("user_id", tracing::field::display(user_id));
+    }
+    let role = get_role(user_id, &pool).await.map_err(e500)?;
+    req.extensions_mut().insert(UserId(user_id));
+    req.extensions_mut().insert(role);
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Only lets editors and owners through. Must be nested behind [`reject_anonymous_users`].
+pub async fn reject_viewers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Only lets owners through. Must be nested behind [`reject_anonymous_users`].
+pub async fn reject_non_owners(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+async fn require_role(
+    required: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let role = req
+        .extensions()
+        .get::<Role>()
+        .copied()
+        .ok_or_else(|| e500("The role of the user is missing from the request."))?;
+    if role < required {
+        FlashMessage::error(format!(
+            "Your role ({}) does not allow you to access this page.",
+            role
+        ))
+        .send();
+        return Ok(req
+            .into_response(see_other("/admin/dashboard"))
+            .map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
@@ -1,3 +1,17 @@
+mod encryption;
+mod middleware;
+mod new_password;
 mod password;
+mod roles;
+mod totp;
+mod two_factor;
+mod user_sessions;
 
+pub use encryption::EncryptionKey;
+pub use middleware::{reject_anonymous_users, reject_non_owners, reject_viewers, UserId};
+pub use new_password::{BreachedPasswords, NewPassword};
 pub use password::*;
+pub use roles::*;
+pub use totp::TotpSecret;
+pub use two_factor::*;
+pub use user_sessions::*;
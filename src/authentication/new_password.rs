@@ -0,0 +1,150 @@
+use secrecy::{ExposeSecret, Secret};
+use std::collections::HashSet;
+use std::path::Path;
+use unicode_segmentation::UnicodeSegmentation;
+
+const MIN_LENGTH: usize = 12;
+const MAX_LENGTH: usize = 128;
+
+/// Fallback for when no `breached_passwords_file` is configured, one password per line.
+const BUNDLED_BREACHED_PASSWORDS: &str = include_str!("breached_passwords.txt");
+
+/// Passwords known to have leaked in public data breaches, lowercased.
+///
+/// Loaded once at startup, from a top-N list such as the ones published by SecLists.
+pub struct BreachedPasswords(HashSet<String>);
+
+impl BreachedPasswords {
+    /// Reads the list at `path`, or the bundled one if there is none.
+    pub fn load(path: Option<&Path>) -> Result<Self, anyhow::Error> {
+        let passwords = match path {
+            Some(path) => {
+                let bytes = std::fs::read(path).map_err(|e| {
+                    anyhow::anyhow!(
+                        "Failed to read the breached passwords from {}: {}",
+                        path.display(),
+                        e
+                    )
+                })?;
+                Self::parse(&String::from_utf8_lossy(&bytes))
+            }
+            None => Self::parse(BUNDLED_BREACHED_PASSWORDS),
+        };
+        tracing::info!("Loaded {} breached passwords", passwords.0.len());
+        Ok(passwords)
+    }
+
+    /// Entries shorter than `MIN_LENGTH` are dropped: they are rejected anyway.
+    fn parse(list: &str) -> Self {
+        Self(
+            list.lines()
+                .map(|line| line.trim().to_lowercase())
+                .filter(|line| line.graphemes(true).count() >= MIN_LENGTH)
+                .collect(),
+        )
+    }
+
+    fn contains(&self, password: &str) -> bool {
+        self.0.contains(&password.to_lowercase())
+    }
+}
+
+#[derive(Debug)]
+pub struct NewPassword(Secret<String>);
+
+impl NewPassword {
+    pub fn parse(
+        s: Secret<String>,
+        breached_passwords: &BreachedPasswords,
+    ) -> Result<NewPassword, String> {
+        let length = s.expose_secret().graphemes(true).count();
+        if length < MIN_LENGTH {
+            return Err(format!(
+                "The new password must be at least {} characters long.",
+                MIN_LENGTH
+            ));
+        }
+        if length > MAX_LENGTH {
+            return Err(format!(
+                "The new password must be at most {} characters long.",
+                MAX_LENGTH
+            ));
+        }
+        if breached_passwords.contains(s.expose_secret()) {
+            return Err(
+                "The new password has appeared in a data breach, please choose another one."
+                    .to_string(),
+            );
+        }
+        Ok(Self(s))
+    }
+}
+
+impl From<NewPassword> for Secret<String> {
+    fn from(password: NewPassword) -> Self {
+        password.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BreachedPasswords, NewPassword};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn parse(s: &str) -> Result<NewPassword, String> {
+        let breached_passwords = BreachedPasswords::load(None).unwrap();
+        NewPassword::parse(Secret::new(s.to_string()), &breached_passwords)
+    }
+
+    #[test]
+    fn a_12_grapheme_long_password_is_valid() {
+        assert_ok!(parse(&"a̐".repeat(12)));
+    }
+
+    #[test]
+    fn a_password_shorter_than_12_graphemes_is_rejected() {
+        assert_err!(parse(&"a̐".repeat(11)));
+    }
+
+    #[test]
+    fn a_128_grapheme_long_password_is_valid() {
+        assert_ok!(parse(&"a̐".repeat(128)));
+    }
+
+    #[test]
+    fn a_password_longer_than_128_graphemes_is_rejected() {
+        assert_err!(parse(&"a̐".repeat(129)));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_regardless_of_case() {
+        assert_err!(parse("password1234"));
+        assert_err!(parse("PassWord1234"));
+    }
+
+    #[test]
+    fn breached_passwords_are_loaded_from_the_configured_file() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(
+            &path,
+            "correct horse battery staple\n  Tr0ub4dor&3xyz  \nshort\n",
+        )
+        .unwrap();
+
+        let breached_passwords = BreachedPasswords::load(Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(breached_passwords.contains("correct horse battery staple"));
+        assert!(breached_passwords.contains("tr0ub4dor&3xyz"));
+        // Entries too short to be accepted anyway are not kept.
+        assert_eq!(breached_passwords.0.len(), 2);
+        // The configured file replaces the bundled list.
+        assert!(!breached_passwords.contains("password1234"));
+    }
+
+    #[test]
+    fn a_missing_breached_passwords_file_is_an_error() {
+        assert!(BreachedPasswords::load(Some("/does/not/exist.txt".as_ref())).is_err());
+    }
+}
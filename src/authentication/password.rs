@@ -1,9 +1,13 @@
 use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
+use std::sync::{Arc, OnceLock};
 
-use crate::telemetry::spawn_blocking_with_tracing;
+use crate::{configuration::PasswordHashSettings, telemetry::spawn_blocking_with_tracing};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -13,6 +17,74 @@ pub enum AuthError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
+/// The Argon2id parameters new password hashes are computed with.
+///
+/// Raising them does not invalidate the existing hashes: they are verified with the parameters
+/// stored alongside them, and recomputed with the current ones on the next successful login.
+#[derive(Clone, Debug)]
+pub struct PasswordHashing {
+    params: Params,
+    /// Verified against when the username is unknown, so that it takes as long as verifying the
+    /// password of an existing user. Computed on first use.
+    dummy_hash: Arc<OnceLock<Secret<String>>>,
+}
+
+impl PasswordHashing {
+    pub fn new(settings: &PasswordHashSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(
+            settings.memory_kib,
+            settings.iterations,
+            settings.parallelism,
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+        Ok(Self {
+            params,
+            dummy_hash: Arc::new(OnceLock::new()),
+        })
+    }
+
+    /// Blocks for as long as hashing a password, the first time it is called.
+    fn dummy_hash(&self) -> Result<Secret<String>, anyhow::Error> {
+        if let Some(dummy_hash) = self.dummy_hash.get() {
+            return Ok(dummy_hash.clone());
+        }
+        let dummy_password = Secret::new(uuid::Uuid::new_v4().to_string());
+        let dummy_hash = self.compute_password_hash(dummy_password)?;
+        Ok(self.dummy_hash.get_or_init(|| dummy_hash).clone())
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    fn compute_password_hash(
+        &self,
+        password: Secret<String>,
+    ) -> Result<Secret<String>, anyhow::Error> {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = self
+            .hasher()
+            .hash_password(password.expose_secret().as_bytes(), &salt)?
+            .to_string();
+        Ok(Secret::new(password_hash))
+    }
+
+    /// Whether a hash was computed with an older algorithm, or weaker parameters than the
+    /// current ones.
+    fn is_outdated(&self, password_hash: &PasswordHash) -> bool {
+        let params = match Params::try_from(password_hash) {
+            Ok(params) => params,
+            Err(_) => return true,
+        };
+        password_hash.algorithm != Algorithm::Argon2id.ident()
+            || password_hash.version != Some(Version::V0x13.into())
+            || params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost()
+    }
+}
+
 pub struct Credentials {
     pub username: String,
     pub password: Secret<String>,
@@ -38,53 +110,238 @@ async fn get_stored_credentials(
     Ok(row)
 }
 
-#[tracing::instrument(name = "Validate credentails", skip(credentials, pool))]
+/// Checks the password of a user. If its stored hash is outdated, it is recomputed with the
+/// current parameters in the background, without slowing the login down.
+#[tracing::instrument(name = "Validate credentails", skip(credentials, hashing, pool))]
 pub async fn validate_credentials(
     credentials: Credentials,
+    hashing: &PasswordHashing,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
-    );
-
-    if let Some((stored_user_id, stored_password_hash)) =
+    let mut stored_password_hash = None;
+
+    if let Some((stored_user_id, password_hash)) =
         get_stored_credentials(&credentials.username, pool).await?
     {
         user_id = Some(stored_user_id);
-        expected_password_hash = stored_password_hash;
+        stored_password_hash = Some(password_hash);
     }
 
-    spawn_blocking_with_tracing(move || {
-        verify_password(expected_password_hash, credentials.password)
+    let hashing = hashing.clone();
+    let outdated_hash = spawn_blocking_with_tracing(move || {
+        let expected_password_hash = match stored_password_hash {
+            Some(password_hash) => password_hash,
+            None => hashing.dummy_hash()?,
+        };
+        let is_outdated =
+            verify_password(&expected_password_hash, &credentials.password, &hashing)?;
+        Ok::<_, AuthError>(is_outdated.then_some((
+            expected_password_hash,
+            credentials.password,
+            hashing,
+        )))
     })
     .await
     .context("Failed to spawn blocking task")??;
 
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow::anyhow!("Unknown username."))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+    if let Some((outdated_hash, password, hashing)) = outdated_hash {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                upgrade_password_hash(user_id, outdated_hash, password, hashing, pool).await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to upgrade a password hash."
+                );
+            }
+        });
+    }
+    Ok(user_id)
 }
 
+/// Returns whether the hash is outdated, if the password matches it.
 #[tracing::instrument(
     name = "Validate credentials",
-    skip(expected_password_hash, password_candidate)
+    skip(expected_password_hash, password_candidate, hashing)
 )]
 fn verify_password(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<(), AuthError> {
+    expected_password_hash: &Secret<String>,
+    password_candidate: &Secret<String>,
+    hashing: &PasswordHashing,
+) -> Result<bool, AuthError> {
     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format.")?;
 
-    Argon2::default()
+    // The hash is verified with the algorithm and parameters stored in it, not the current ones.
+    hashing
+        .hasher()
         .verify_password(
             password_candidate.expose_secret().as_bytes(),
             &expected_password_hash,
         )
         .context("Invalid password.")
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+    Ok(hashing.is_outdated(&expected_password_hash))
+}
+
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(outdated_hash, password, hashing, pool)
+)]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    outdated_hash: Secret<String>,
+    password: Secret<String>,
+    hashing: PasswordHashing,
+    pool: PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = hash_password(password, &hashing).await?;
+    // The password may have been changed in the meantime: only the hash that was verified is
+    // replaced.
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+        outdated_hash.expose_secret()
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to store the upgraded password hash.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Change password", skip(password, hashing, pool))]
+pub async fn change_password(
+    user_id: uuid::Uuid,
+    password: Secret<String>,
+    hashing: &PasswordHashing,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = hash_password(password, hashing).await?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    Ok(())
+}
+
+pub async fn hash_password(
+    password: Secret<String>,
+    hashing: &PasswordHashing,
+) -> Result<Secret<String>, anyhow::Error> {
+    let hashing = hashing.clone();
+    spawn_blocking_with_tracing(move || hashing.compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{
+        password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version,
+    };
+    use secrecy::ExposeSecret;
+
+    use super::PasswordHashing;
+    use crate::configuration::PasswordHashSettings;
+
+    // Cheap parameters, these tests only look at the PHC strings.
+    fn hashing() -> PasswordHashing {
+        PasswordHashing::new(&PasswordHashSettings {
+            memory_kib: 64,
+            iterations: 2,
+            parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    fn hash_with(algorithm: Algorithm, version: Version, m_cost: u32, t_cost: u32) -> String {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        Argon2::new(
+            algorithm,
+            version,
+            Params::new(m_cost, t_cost, 1, None).unwrap(),
+        )
+        .hash_password(b"password", &salt)
+        .unwrap()
+        .to_string()
+    }
+
+    fn is_outdated(password_hash: &str) -> bool {
+        hashing().is_outdated(&PasswordHash::new(password_hash).unwrap())
+    }
+
+    #[test]
+    fn hashes_with_the_current_or_stronger_parameters_are_kept() {
+        assert!(!is_outdated(&hash_with(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            64,
+            2
+        )));
+        assert!(!is_outdated(&hash_with(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            128,
+            3
+        )));
+    }
+
+    #[test]
+    fn hashes_with_weaker_parameters_are_outdated() {
+        assert!(is_outdated(&hash_with(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            32,
+            2
+        )));
+        assert!(is_outdated(&hash_with(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            128,
+            1
+        )));
+    }
+
+    #[test]
+    fn hashes_with_an_older_algorithm_are_outdated() {
+        assert!(is_outdated(&hash_with(
+            Algorithm::Argon2i,
+            Version::V0x13,
+            64,
+            2
+        )));
+        assert!(is_outdated(&hash_with(
+            Algorithm::Argon2id,
+            Version::V0x10,
+            64,
+            2
+        )));
+    }
+
+    #[test]
+    fn the_dummy_hash_uses_the_current_parameters() {
+        let hashing = hashing();
+        let dummy_hash = hashing.dummy_hash().unwrap();
+        let dummy_hash = PasswordHash::new(dummy_hash.expose_secret()).unwrap();
+        assert!(!hashing.is_outdated(&dummy_hash));
+    }
 }
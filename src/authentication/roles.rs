@@ -0,0 +1,68 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// What an admin is allowed to do. Each role can do everything the roles below it can:
+/// viewers can only look around, editors publish and manage subscribers, owners manage users.
+#[derive(serde::Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Editor, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "owner" => Ok(Role::Owner),
+            other => Err(anyhow::anyhow!("Unknown role: {}", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[tracing::instrument(name = "Get the role of a user", skip(pool))]
+pub async fn get_role(user_id: Uuid, pool: &PgPool) -> Result<Role, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT role FROM users WHERE user_id = $1"#, user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to retrieve the role of a user.")?;
+    Role::parse(&row.role)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+
+    #[test]
+    fn roles_are_ordered_by_privilege() {
+        assert!(Role::Owner > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_database_representation() {
+        for role in Role::ALL {
+            assert_eq!(Role::parse(role.as_str()).unwrap(), role);
+        }
+        assert!(Role::parse("admin").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Time-based one-time passwords (RFC 6238), as generated by authenticator apps.
+//!
+//! We use the parameters every app supports: HMAC-SHA1, 6 digits and a 30 second time step.
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+const SECRET_LENGTH: usize = 20;
+const DIGITS: u32 = 6;
+const TIME_STEP_SECONDS: u64 = 30;
+/// Codes from the previous and next time steps are accepted too, to make up for clock drift
+/// and for the time it takes to type a code in.
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+const ISSUER: &str = "zero2prod";
+
+pub const RECOVERY_CODES_COUNT: usize = 10;
+
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self(secret)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn from_base32(s: &str) -> Result<Self, anyhow::Error> {
+        let bytes = BASE32_NOPAD
+            .decode(s.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Invalid base32 TOTP secret: {}", e))?;
+        Ok(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The secret as users type it in when they cannot scan the QR code.
+    pub fn to_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.0)
+    }
+
+    /// The `otpauth://` URI understood by authenticator apps, encoded in the QR code.
+    pub fn provisioning_uri(&self, username: &str) -> String {
+        let mut uri = reqwest::Url::parse("otpauth://totp/").unwrap();
+        uri.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .push(&format!("{}:{}", ISSUER, username));
+        uri.query_pairs_mut()
+            .append_pair("secret", &self.to_base32())
+            .append_pair("issuer", ISSUER)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &TIME_STEP_SECONDS.to_string());
+        uri.to_string()
+    }
+
+    /// The code for the time step containing `unix_time`.
+    pub fn code_at(&self, unix_time: u64) -> String {
+        hotp(&self.0, unix_time / TIME_STEP_SECONDS, DIGITS)
+    }
+
+    /// Returns the time step the code belongs to, if it is valid at `unix_time`.
+    ///
+    /// Codes from `last_used_step` or earlier are rejected: each code can only be used once.
+    pub fn verify(&self, code: &str, unix_time: u64, last_used_step: Option<i64>) -> Option<i64> {
+        let code = code.trim();
+        let current_step = unix_time / TIME_STEP_SECONDS;
+        (current_step.saturating_sub(ALLOWED_DRIFT_STEPS)..=current_step + ALLOWED_DRIFT_STEPS)
+            .filter(|step| last_used_step.is_none_or(|last| *step as i64 > last))
+            .find(|step| hotp(&self.0, *step, DIGITS) == code)
+            .map(|step| step as i64)
+    }
+}
+
+/// HOTP (RFC 4226), the building block of TOTP: the counter is the current time step.
+fn hotp(key: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// Recovery codes look like `abcde-12345`, they are meant to be written down.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODES_COUNT)
+        .map(|_| {
+            let code: String = (&mut rng)
+                .sample_iter(Alphanumeric)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .take(10)
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are random enough for a fast hash to be safe. Case and separators are
+/// ignored, they are easy to get wrong when copying a code by hand.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_codes, hash_recovery_code, hotp, TotpSecret};
+
+    // The SHA1 test vectors from RFC 6238, appendix B.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        for (time, expected) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(hotp(RFC_SECRET, time / 30, 8), expected);
+        }
+    }
+
+    #[test]
+    fn six_digit_codes_are_the_last_digits_of_the_rfc_vectors() {
+        let secret = TotpSecret::from_bytes(RFC_SECRET.to_vec());
+        assert_eq!(secret.code_at(59), "287082");
+        assert_eq!(secret.code_at(1234567890), "005924");
+    }
+
+    #[test]
+    fn codes_from_adjacent_time_steps_are_accepted_once() {
+        let secret = TotpSecret::from_bytes(RFC_SECRET.to_vec());
+        let now = 1111111111;
+        let previous_code = secret.code_at(now - 30);
+        let step = secret.verify(&previous_code, now, None).unwrap();
+        assert_eq!(step, (now as i64 - 30) / 30);
+        assert_eq!(secret.verify(&previous_code, now, Some(step)), None);
+        assert_eq!(secret.verify(&secret.code_at(now - 90), now, None), None);
+    }
+
+    #[test]
+    fn the_provisioning_uri_contains_the_base32_secret() {
+        let secret = TotpSecret::from_bytes(RFC_SECRET.to_vec());
+        assert_eq!(
+            secret.provisioning_uri("ursula le guin"),
+            "otpauth://totp/zero2prod:ursula%20le%20guin\
+             ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=zero2prod\
+             &algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_and_hashed_loosely() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        assert_eq!(
+            codes.iter().collect::<std::collections::HashSet<_>>().len(),
+            10
+        );
+        assert_eq!(
+            hash_recovery_code("ABCDE-12345"),
+            hash_recovery_code(" abcde12345 ")
+        );
+    }
+}
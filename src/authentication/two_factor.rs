@@ -0,0 +1,301 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{
+    encryption::EncryptionKey,
+    totp::{generate_recovery_codes, hash_recovery_code, TotpSecret},
+};
+
+/// Past this many wrong codes in a row, the login starts over from the password.
+pub const MAX_FAILED_SECOND_FACTOR_ATTEMPTS: i32 = 5;
+
+/// How a user got past the second step of the login.
+#[derive(Debug)]
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode { remaining: i64 },
+}
+
+/// The outcome of a code typed in at the second step of the login.
+#[derive(Debug)]
+pub enum SecondFactorCheck {
+    Valid(SecondFactor),
+    Invalid,
+    /// The code is wrong and there were too many wrong codes in a row: the count starts over and
+    /// so must the login.
+    TooManyInvalid,
+}
+
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// A secret generated for the user that has not been confirmed with a code yet.
+    pub pending_secret: Option<TotpSecret>,
+    pub recovery_codes_left: i64,
+}
+
+#[tracing::instrument(name = "Check if two-factor authentication is enabled", skip(pool))]
+pub async fn is_two_factor_enabled(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret IS NOT NULL AS "enabled!" FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to check if two-factor authentication is enabled.")?;
+    Ok(row.enabled)
+}
+
+#[tracing::instrument(name = "Get the two-factor authentication status", skip(key, pool))]
+pub async fn get_two_factor_status(
+    user_id: Uuid,
+    key: &EncryptionKey,
+    pool: &PgPool,
+) -> Result<TwoFactorStatus, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            totp_secret IS NOT NULL AS "enabled!",
+            totp_pending_secret,
+            (SELECT COUNT(*) FROM totp_recovery_codes WHERE user_id = $1) AS "recovery_codes_left!"
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the two-factor authentication status.")?;
+    let pending_secret = row
+        .totp_pending_secret
+        .map(|secret| key.decrypt(&secret))
+        .transpose()?
+        .map(TotpSecret::from_bytes);
+    Ok(TwoFactorStatus {
+        enabled: row.enabled,
+        pending_secret,
+        recovery_codes_left: row.recovery_codes_left,
+    })
+}
+
+/// Generates a new secret for the user to add to their authenticator app. It is only used for
+/// logging in once [`confirm_enrolment`] has been called with a code generated from it.
+#[tracing::instrument(name = "Start a two-factor authentication enrolment", skip(key, pool))]
+pub async fn start_enrolment(
+    user_id: Uuid,
+    key: &EncryptionKey,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let secret = key.encrypt(TotpSecret::generate().as_bytes())?;
+    sqlx::query!(
+        r#"UPDATE users SET totp_pending_secret = $1 WHERE user_id = $2"#,
+        secret,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a pending TOTP secret.")?;
+    Ok(())
+}
+
+/// Enables two-factor authentication if the code matches the pending secret, returning a fresh
+/// set of recovery codes. They are only stored hashed, this is the one chance to show them.
+#[tracing::instrument(
+    name = "Confirm a two-factor authentication enrolment",
+    skip(code, key, pool)
+)]
+pub async fn confirm_enrolment(
+    user_id: Uuid,
+    code: &str,
+    key: &EncryptionKey,
+    pool: &PgPool,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let mut transaction = begin(pool).await?;
+    let row = sqlx::query!(
+        r#"SELECT totp_pending_secret FROM users WHERE user_id = $1 FOR UPDATE"#,
+        user_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to retrieve the pending TOTP secret.")?;
+    let encrypted_secret = match row.totp_pending_secret {
+        Some(secret) => secret,
+        None => return Ok(None),
+    };
+    let secret = TotpSecret::from_bytes(key.decrypt(&encrypted_secret)?);
+    let step = match secret.verify(code, now(), None) {
+        Some(step) => step,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = totp_pending_secret, totp_pending_secret = NULL, totp_last_used_step = $1
+        WHERE user_id = $2
+        "#,
+        step,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable two-factor authentication.")?;
+    let recovery_codes = replace_recovery_codes(&mut transaction, user_id).await?;
+    commit(transaction).await?;
+    Ok(Some(recovery_codes))
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = begin(pool).await?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_pending_secret = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to disable two-factor authentication.")?;
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the recovery codes.")?;
+    commit(transaction).await
+}
+
+/// Checks the code typed in at the second step of the login, either from the authenticator app
+/// or one of the recovery codes. Either way, the code cannot be used again.
+///
+/// Wrong codes are counted per user rather than per login, so that starting the login over does
+/// not grant more attempts.
+#[tracing::instrument(name = "Verify a second factor", skip(code, key, pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    key: &EncryptionKey,
+    pool: &PgPool,
+) -> Result<SecondFactorCheck, anyhow::Error> {
+    let mut transaction = begin(pool).await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_used_step, totp_failed_attempts
+        FROM users
+        WHERE user_id = $1
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to retrieve the TOTP secret.")?;
+    let encrypted_secret = row
+        .totp_secret
+        .context("Two-factor authentication is not enabled for this user.")?;
+    let secret = TotpSecret::from_bytes(key.decrypt(&encrypted_secret)?);
+
+    let second_factor = if let Some(step) = secret.verify(code, now(), row.totp_last_used_step) {
+        sqlx::query!(
+            r#"UPDATE users SET totp_last_used_step = $1 WHERE user_id = $2"#,
+            step,
+            user_id
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to record the last used TOTP time step.")?;
+        Some(SecondFactor::Totp)
+    } else {
+        let used = sqlx::query!(
+            r#"DELETE FROM totp_recovery_codes WHERE user_id = $1 AND code_hash = $2"#,
+            user_id,
+            hash_recovery_code(code)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to use a recovery code.")?
+        .rows_affected()
+            > 0;
+        if used {
+            let remaining = sqlx::query!(
+                r#"SELECT COUNT(*) AS "count!" FROM totp_recovery_codes WHERE user_id = $1"#,
+                user_id
+            )
+            .fetch_one(&mut transaction)
+            .await
+            .context("Failed to count the remaining recovery codes.")?
+            .count;
+            Some(SecondFactor::RecoveryCode { remaining })
+        } else {
+            None
+        }
+    };
+    let (check, failed_attempts) = match second_factor {
+        Some(second_factor) => (SecondFactorCheck::Valid(second_factor), 0),
+        None if row.totp_failed_attempts + 1 >= MAX_FAILED_SECOND_FACTOR_ATTEMPTS => {
+            (SecondFactorCheck::TooManyInvalid, 0)
+        }
+        None => (SecondFactorCheck::Invalid, row.totp_failed_attempts + 1),
+    };
+    sqlx::query!(
+        r#"UPDATE users SET totp_failed_attempts = $1 WHERE user_id = $2"#,
+        failed_attempts,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to record the failed second factor attempts.")?;
+    commit(transaction).await?;
+    Ok(check)
+}
+
+async fn replace_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the previous recovery codes.")?;
+    let recovery_codes = generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_recovery_codes (user_id, code_hash)
+        SELECT $1, code_hash FROM UNNEST($2::text[]) AS code_hash
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the recovery codes.")?;
+    Ok(recovery_codes)
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, anyhow::Error> {
+    pool.begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+}
+
+async fn commit(transaction: Transaction<'_, Postgres>) -> Result<(), anyhow::Error> {
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update two-factor authentication.")
+}
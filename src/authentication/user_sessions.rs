@@ -0,0 +1,106 @@
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// Every admin session carries a `session_id` that must have a matching row in `user_sessions`.
+// Deleting the row invalidates the session server-side, even if we cannot reach the session store
+// entry it belongs to.
+
+/// How long the session state lives in the session store, and its row in `user_sessions`.
+pub const SESSION_TTL_HOURS: i64 = 24;
+
+#[tracing::instrument(name = "Register a new user session", skip(pool))]
+pub async fn register_session(user_id: Uuid, pool: &PgPool) -> Result<Uuid, anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (session_id, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        session_id,
+        user_id,
+        now,
+        now + Duration::hours(SESSION_TTL_HOURS)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to register a new user session.")?;
+    Ok(session_id)
+}
+
+#[tracing::instrument(name = "Check if a user session is active", skip(pool))]
+pub async fn is_session_active(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT session_id
+        FROM user_sessions
+        WHERE session_id = $1 AND user_id = $2 AND expires_at > now()
+        "#,
+        session_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user session.")?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Revoke a user session", skip(pool))]
+pub async fn revoke_session(session_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE session_id = $1"#,
+        session_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a user session.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Revoke all other user sessions", skip(pool))]
+pub async fn revoke_other_sessions(
+    user_id: Uuid,
+    current_session_id: Uuid,
+    pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE user_id = $1 AND session_id <> $2"#,
+        user_id,
+        current_session_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke the other sessions of a user.")?;
+    Ok(result.rows_affected())
+}
+
+/// Logs a user out everywhere, as part of a larger change to their account.
+#[tracing::instrument(name = "Revoke all user sessions", skip(transaction))]
+pub async fn revoke_all_sessions(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(r#"DELETE FROM user_sessions WHERE user_id = $1"#, user_id)
+        .execute(transaction)
+        .await
+        .context("Failed to revoke the sessions of a user.")?;
+    Ok(result.rows_affected())
+}
+
+/// Deletes the rows of the sessions whose state has expired from the session store.
+#[tracing::instrument(name = "Delete expired user sessions", skip_all, err)]
+pub async fn delete_expired_sessions(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_expired_sessions = sqlx::query!(r#"DELETE FROM user_sessions WHERE expires_at <= now()"#)
+        .execute(pool)
+        .await
+        .context("Failed to delete the expired user sessions.")?
+        .rows_affected();
+    tracing::info!(n_expired_sessions, "Cleaned up expired user sessions.");
+    Ok(n_expired_sessions)
+}
@@ -1,17 +1,37 @@
+use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions,
 };
-use std::convert::{TryFrom, TryInto};
+use std::{
+    convert::{TryFrom, TryInto},
+    net::IpAddr,
+    path::PathBuf,
+    sync::Arc,
+};
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailSender, FileSinkEmailClient, PostmarkEmailClient, SmtpEmailClient},
+    login_throttle::LoginAttemptStoreKind,
+    session_store::SessionStoreKind,
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub redis_uri: Secret<String>,
+    #[serde(default)]
+    pub session_store: SessionStoreKind,
+    pub login_throttle: LoginThrottleSettings,
+    pub password_hashing: PasswordHashSettings,
+    /// A list of breached passwords, one per line, that new passwords are checked against.
+    /// Without one, a short bundled list is used.
+    #[serde(default)]
+    pub breached_passwords_file: Option<PathBuf>,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -20,6 +40,47 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub encryption_key: Secret<String>,
+}
+
+/// How failed logins slow down, then lock out, further attempts. See `LoginThrottle`.
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottleSettings {
+    #[serde(default)]
+    pub store: LoginAttemptStoreKind,
+    /// Failures older than this are forgotten.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+    /// Failures for a username before attempts start being delayed.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub free_attempts: u32,
+    /// The delay after the first failure past the free ones, doubled by every other failure.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_username: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_address: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: u64,
+    /// Proxies, e.g. the load balancer, whose `X-Forwarded-For` header is trusted to tell the
+    /// address of the client. Without any, the address of the peer is used.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Argon2id parameters for new password hashes. See `PasswordHashing`.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordHashSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -116,15 +177,75 @@ impl TryFrom<String> for Environment {
     }
 }
 
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailClientKind {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub kind: EmailClientKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: String,
     pub timeout_milliseconds: u64,
+    pub smtp: SmtpSettings,
+    pub file_sink_directory: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: String,
+    pub password: Secret<String>,
+    pub require_tls: bool,
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> Result<Arc<dyn EmailSender>, anyhow::Error> {
+        let sender_email = self
+            .sender()
+            .map_err(|e| anyhow::anyhow!("Invalid sender email address: {}", e))?;
+        let timeout = self.timeout();
+        let client: Arc<dyn EmailSender> = match self.kind {
+            EmailClientKind::Postmark => Arc::new(PostmarkEmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailClientKind::Smtp => {
+                // An empty username means the relay does not require authentication.
+                let credentials = (!self.smtp.username.is_empty()).then(|| {
+                    lettre::transport::smtp::authentication::Credentials::new(
+                        self.smtp.username.clone(),
+                        self.smtp.password.expose_secret().clone(),
+                    )
+                });
+                Arc::new(SmtpEmailClient::new(
+                    &self.smtp.host,
+                    self.smtp.port,
+                    credentials,
+                    self.smtp.require_tls,
+                    sender_email,
+                    timeout,
+                )?)
+            }
+            EmailClientKind::File => Arc::new(FileSinkEmailClient::new(
+                self.file_sink_directory.into(),
+                sender_email,
+            )),
+        };
+        Ok(client)
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
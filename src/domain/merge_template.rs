@@ -0,0 +1,193 @@
+use askama::{Html, MarkupDisplay};
+use chrono::{DateTime, Utc};
+
+/// Newsletter content with merge fields, e.g. `Hi {{ name }}!`, filled in for every subscriber.
+///
+/// Parsing rejects unknown or unterminated merge fields: a typo must be caught when the issue is
+/// published, not discovered by subscribers in their inbox.
+#[derive(Debug)]
+pub struct MergeTemplate {
+    content: String,
+    literal: bool,
+}
+
+/// The values of the merge fields for a single subscriber.
+pub struct MergeFields<'a> {
+    pub name: &'a str,
+    pub subscribed_at: DateTime<Utc>,
+    pub unsubscribe_url: &'a str,
+}
+
+const KNOWN_FIELDS: [&str; 3] = ["name", "subscribed_at", "unsubscribe_url"];
+
+enum Segment<'a> {
+    Literal(&'a str),
+    Field(&'a str),
+}
+
+impl MergeTemplate {
+    pub fn parse(s: String) -> Result<MergeTemplate, String> {
+        for segment in segments(&s) {
+            match segment? {
+                Segment::Field(field) if !KNOWN_FIELDS.contains(&field) => {
+                    return Err(format!(
+                        "`{{{{ {} }}}}` is not a known merge field. The available fields are: {}.",
+                        field,
+                        KNOWN_FIELDS.join(", ")
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            content: s,
+            literal: false,
+        })
+    }
+
+    /// Content without merge fields, e.g. an issue published before they existed: it is
+    /// delivered as-is, `{{` included.
+    pub fn literal(s: String) -> MergeTemplate {
+        Self {
+            content: s,
+            literal: true,
+        }
+    }
+
+    /// Fills in the merge fields, escaping their values for HTML.
+    pub fn render_html(&self, fields: &MergeFields) -> String {
+        self.render(fields, |value| {
+            MarkupDisplay::new_unsafe(value, Html).to_string()
+        })
+    }
+
+    pub fn render_text(&self, fields: &MergeFields) -> String {
+        self.render(fields, |value| value.to_owned())
+    }
+
+    fn render(&self, fields: &MergeFields, escape: impl Fn(&str) -> String) -> String {
+        if self.literal {
+            return self.content.clone();
+        }
+        let mut rendered = String::with_capacity(self.content.len());
+        // The template has been validated in `parse`, every segment is well-formed.
+        for segment in segments(&self.content).flatten() {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Field("name") => rendered.push_str(&escape(fields.name)),
+                Segment::Field("subscribed_at") => rendered.push_str(&escape(
+                    &fields.subscribed_at.format("%B %-d, %Y").to_string(),
+                )),
+                Segment::Field("unsubscribe_url") => {
+                    rendered.push_str(&escape(fields.unsubscribe_url))
+                }
+                Segment::Field(_) => unreachable!("Unknown merge fields are rejected by `parse`."),
+            }
+        }
+        rendered
+    }
+}
+
+impl AsRef<str> for MergeTemplate {
+    fn as_ref(&self) -> &str {
+        &self.content
+    }
+}
+
+/// Splits a template into literal text and `{{ field }}` placeholders.
+fn segments(s: &str) -> impl Iterator<Item = Result<Segment<'_>, String>> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let segment = match rest.find("{{") {
+            Some(0) => match rest.find("}}") {
+                Some(end) => {
+                    let field = rest[2..end].trim();
+                    rest = &rest[end + 2..];
+                    Ok(Segment::Field(field))
+                }
+                None => {
+                    rest = "";
+                    Err("A merge field is missing its closing `}}`.".to_string())
+                }
+            },
+            Some(start) => {
+                let literal = &rest[..start];
+                rest = &rest[start..];
+                Ok(Segment::Literal(literal))
+            }
+            None => {
+                let literal = rest;
+                rest = "";
+                Ok(Segment::Literal(literal))
+            }
+        };
+        Some(segment)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+
+    use super::{MergeFields, MergeTemplate};
+
+    fn fields() -> MergeFields<'static> {
+        MergeFields {
+            name: "Ursula & co",
+            subscribed_at: Utc.with_ymd_and_hms(2023, 3, 5, 12, 0, 0).unwrap(),
+            unsubscribe_url: "https://example.com/unsubscribe",
+        }
+    }
+
+    #[test]
+    fn content_without_merge_fields_is_valid_and_left_untouched() {
+        let template = MergeTemplate::parse("Just some { text }".into()).unwrap();
+        assert_eq!(template.render_text(&fields()), "Just some { text }");
+    }
+
+    #[test]
+    fn known_merge_fields_are_filled_in() {
+        let template = MergeTemplate::parse(
+            "Hi {{ name }}, subscribed on {{subscribed_at}}. Leave: {{ unsubscribe_url }}".into(),
+        )
+        .unwrap();
+        assert_eq!(
+            template.render_text(&fields()),
+            "Hi Ursula & co, subscribed on March 5, 2023. Leave: https://example.com/unsubscribe"
+        );
+    }
+
+    #[test]
+    fn merge_fields_are_escaped_in_html() {
+        let template = MergeTemplate::parse("<p>Hi {{ name }}</p>".into()).unwrap();
+        assert_eq!(template.render_html(&fields()), "<p>Hi Ursula &amp; co</p>");
+    }
+
+    #[test]
+    fn unknown_merge_fields_are_rejected() {
+        assert_err!(MergeTemplate::parse("Hi {{ first_name }}".into()));
+    }
+
+    #[test]
+    fn unterminated_merge_fields_are_rejected() {
+        assert_err!(MergeTemplate::parse("Hi {{ name".into()));
+    }
+
+    #[test]
+    fn literal_content_is_left_untouched() {
+        let template = MergeTemplate::literal("<p>Hi {{ name }} {{ oops</p>".into());
+        assert_eq!(
+            template.render_html(&fields()),
+            "<p>Hi {{ name }} {{ oops</p>"
+        );
+    }
+
+    #[test]
+    fn a_closing_brace_pair_alone_is_literal_text() {
+        assert_ok!(MergeTemplate::parse("Hi }} there".into()));
+    }
+}
@@ -1,7 +1,9 @@
+mod merge_template;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use merge_template::{MergeFields, MergeTemplate};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use chrono::Utc;
+
+use super::{build_message, EmailError, EmailSender, OutgoingEmail};
+use crate::domain::SubscriberEmail;
+
+/// Writes every email as an `.eml` file in a local directory instead of sending it.
+///
+/// Meant for local development: the files can be opened with any mail client to check what
+/// subscribers would have received.
+pub struct FileSinkEmailClient {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkEmailClient {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        Self { directory, sender }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSinkEmailClient {
+    async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), EmailError> {
+        let message = build_message(&self.sender, email)?;
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            uuid::Uuid::new_v4()
+        );
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| EmailError::Transient(e.into()))?;
+        tokio::fs::write(self.directory.join(file_name), message.formatted())
+            .await
+            .map_err(|e| EmailError::Transient(e.into()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok;
+    use fake::{faker::internet::en::SafeEmail, Fake};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, FileSinkEmailClient, OutgoingEmail};
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn emails_are_written_to_the_sink_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = FileSinkEmailClient::new(directory.clone(), email());
+        let recipient = email();
+
+        // Act
+        let outcome = email_client
+            .send(&OutgoingEmail {
+                recipient: &recipient,
+                subject: "Issue #1",
+                html_content: "<p>Content</p>",
+                text_content: "Content",
+                unsubscribe_url: Some("https://example.com/unsubscribe"),
+            })
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(contents.contains("Subject: Issue #1"));
+        assert!(contents.contains(&format!("To: {}", recipient.as_ref())));
+        assert!(contents.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(contents.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
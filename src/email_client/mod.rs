@@ -0,0 +1,136 @@
+mod file_sink;
+mod postmark;
+mod smtp;
+
+pub use file_sink::FileSinkEmailClient;
+pub use postmark::PostmarkEmailClient;
+pub use smtp::SmtpEmailClient;
+
+use lettre::message::{
+    header::{HeaderName, HeaderValue},
+    Mailbox, MultiPart,
+};
+
+use crate::domain::SubscriberEmail;
+
+/// A transport able to deliver emails on our behalf.
+///
+/// Route handlers and the delivery worker only ever see a `dyn EmailSender`: which implementation
+/// backs it is decided by `EmailClientSettings::kind`.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), EmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), EmailError> {
+        self.send(&OutgoingEmail {
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_url: None,
+        })
+        .await
+    }
+
+    /// Sends several emails at once, returning one outcome per email in the same order.
+    ///
+    /// Transports without a batch API fall back to sending the emails one by one.
+    async fn send_batch(&self, emails: &[OutgoingEmail<'_>]) -> Vec<Result<(), EmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+}
+
+pub struct OutgoingEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    /// Advertised through the `List-Unsubscribe` and `List-Unsubscribe-Post` headers (RFC 8058),
+    /// so that mail clients can offer a one-click unsubscribe button.
+    pub unsubscribe_url: Option<&'a str>,
+}
+
+impl OutgoingEmail<'_> {
+    /// The extra headers to attach to the email, as (name, value) pairs.
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        match self.unsubscribe_url {
+            Some(url) => vec![
+                ("List-Unsubscribe", format!("<{}>", url)),
+                (
+                    "List-Unsubscribe-Post",
+                    "List-Unsubscribe=One-Click".to_owned(),
+                ),
+            ],
+            None => vec![],
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    /// Timeouts, connection failures, rate limiting, provider outages: trying again later might
+    /// succeed.
+    #[error("Failed to send the email, the failure is likely temporary.")]
+    Transient(#[source] anyhow::Error),
+    /// The message itself was rejected (e.g. an invalid recipient): sending it again will not
+    /// change the outcome.
+    #[error("The email was rejected.")]
+    Permanent(#[source] anyhow::Error),
+}
+
+impl EmailError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, EmailError::Transient(_))
+    }
+
+    /// A copy of the error, for failures that affect several emails at once (e.g. a whole batch
+    /// being rejected). The cause chain is flattened into a single message.
+    fn duplicate(&self) -> Self {
+        match self {
+            EmailError::Transient(e) => EmailError::Transient(anyhow::anyhow!("{:#}", e)),
+            EmailError::Permanent(e) => EmailError::Permanent(anyhow::anyhow!("{:#}", e)),
+        }
+    }
+}
+
+/// Builds a `multipart/alternative` message, shared by the transports that speak MIME.
+fn build_message(
+    sender: &SubscriberEmail,
+    email: &OutgoingEmail<'_>,
+) -> Result<lettre::Message, EmailError> {
+    let from: Mailbox = sender
+        .as_ref()
+        .parse()
+        .map_err(|e| EmailError::Permanent(anyhow::Error::new(e)))?;
+    let to: Mailbox = email
+        .recipient
+        .as_ref()
+        .parse()
+        .map_err(|e| EmailError::Permanent(anyhow::Error::new(e)))?;
+    let mut builder = lettre::Message::builder()
+        .from(from)
+        .to(to)
+        .subject(email.subject);
+    for (name, value) in email.headers() {
+        builder = builder.raw_header(HeaderValue::new(
+            HeaderName::new_from_ascii_str(name),
+            value,
+        ));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_content.to_owned(),
+            email.html_content.to_owned(),
+        ))
+        .map_err(|e| EmailError::Permanent(anyhow::Error::new(e)))
+}
@@ -0,0 +1,539 @@
+use reqwest::{Client, StatusCode};
+
+use super::{EmailError, EmailSender, OutgoingEmail};
+use crate::domain::SubscriberEmail;
+
+/// Postmark accepts at most this many messages in a single call to the batch endpoint.
+const MAX_BATCH_SIZE: usize = 500;
+
+pub struct PostmarkEmailClient {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: String,
+}
+
+impl PostmarkEmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: String,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkEmailClient {
+    async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), EmailError> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest::new(&self.sender, email);
+        self.http_client
+            .post(&url)
+            .header("X-Postmark-Server-Token", &self.authorization_token)
+            .json(&request_body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(classify_error)?;
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[OutgoingEmail<'_>]) -> Vec<Result<(), EmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                Err(e) => outcomes.extend(chunk.iter().map(|_| Err(e.duplicate()))),
+            }
+        }
+        outcomes
+    }
+}
+
+impl PostmarkEmailClient {
+    async fn send_chunk(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Result<Vec<Result<(), EmailError>>, EmailError> {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = emails
+            .iter()
+            .map(|email| SendEmailRequest::new(&self.sender, email))
+            .collect();
+        let response = self
+            .http_client
+            .post(&url)
+            .header("X-Postmark-Server-Token", &self.authorization_token)
+            .json(&request_body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(classify_error)?;
+        // Postmark has accepted the batch at this point: if we cannot make sense of its answer,
+        // retrying could deliver the same emails twice, so the failure is treated as permanent.
+        let results: Vec<BatchResult> = response
+            .json()
+            .await
+            .map_err(|e| EmailError::Permanent(e.into()))?;
+        if results.len() != emails.len() {
+            return Err(EmailError::Permanent(anyhow::anyhow!(
+                "Postmark returned {} results for a batch of {} emails.",
+                results.len(),
+                emails.len()
+            )));
+        }
+        let outcomes = results
+            .into_iter()
+            .map(|result| match result.error_code {
+                0 => Ok(()),
+                error_code => Err(EmailError::Permanent(anyhow::anyhow!(
+                    "Postmark rejected the email (error code {}): {}",
+                    error_code,
+                    result.message
+                ))),
+            })
+            .collect();
+        Ok(outcomes)
+    }
+}
+
+/// Timeouts, connection failures, rate limiting (429) and server errors (5xx) are transient. Any
+/// other 4xx means Postmark rejected the message itself (e.g. an invalid recipient).
+fn classify_error(e: reqwest::Error) -> EmailError {
+    let is_transient = match e.status() {
+        Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        None => true,
+    };
+    if is_transient {
+        EmailError::Transient(e.into())
+    } else {
+        EmailError::Permanent(e.into())
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header>,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(sender: &'a SubscriberEmail, email: &OutgoingEmail<'a>) -> Self {
+        Self {
+            from: sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: email.subject,
+            html_body: email.html_content,
+            text_body: email.text_content,
+            headers: email
+                .headers()
+                .into_iter()
+                .map(|(name, value)| Header { name, value })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header {
+    name: &'static str,
+    value: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResult {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod test {
+    use claims::{assert_err, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use wiremock::{
+        matchers::{any, header, header_exists, method, path},
+        Mock, MockServer, Request, Respond, ResponseTemplate,
+    };
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, OutgoingEmail, PostmarkEmailClient};
+
+    struct SendEmailBodyMatcher;
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> PostmarkEmailClient {
+        PostmarkEmailClient::new(
+            base_url,
+            email(),
+            Faker.fake(),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_base_url() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn server_errors_and_rate_limiting_are_transient() {
+        for status_code in [429, 500, 503] {
+            // Arrange
+            let mock_server = MockServer::start().await;
+            let email_client = email_client(mock_server.uri());
+
+            Mock::given(any())
+                .respond_with(ResponseTemplate::new(status_code))
+                .mount(&mock_server)
+                .await;
+
+            // Act
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+
+            // Assert
+            assert!(outcome.unwrap_err().is_transient());
+        }
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_transient() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_permanent() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(!outcome.unwrap_err().is_transient());
+    }
+
+    /// Mimics Postmark's batch endpoint: every message is accepted, except those addressed to
+    /// `rejected_recipient`.
+    struct BatchResponder {
+        rejected_recipient: String,
+    }
+
+    impl Respond for BatchResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|message| {
+                    if message["To"] == self.rejected_recipient.as_str() {
+                        serde_json::json!({"ErrorCode": 406, "Message": "Inactive recipient"})
+                    } else {
+                        serde_json::json!({"ErrorCode": 0, "Message": "OK"})
+                    }
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_every_email() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..3).map(|_| email()).collect();
+        let (subject, content) = (subject(), content());
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                unsubscribe_url: None,
+            })
+            .collect();
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(BatchResponder {
+                rejected_recipient: recipients[1].as_ref().to_owned(),
+            })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 3);
+        assert_ok!(&outcomes[0]);
+        assert!(!outcomes[1].as_ref().unwrap_err().is_transient());
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+        let emails: Vec<_> = (0..501)
+            .map(|_| OutgoingEmail {
+                recipient: &recipient,
+                subject: "Subject",
+                html_content: "<p>Content</p>",
+                text_content: "Content",
+                unsubscribe_url: None,
+            })
+            .collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder {
+                rejected_recipient: String::new(),
+            })
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 501);
+        assert!(outcomes.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_fails_every_email_in_it() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..2).map(|_| email()).collect();
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: "Subject",
+                html_content: "<p>Content</p>",
+                text_content: "Content",
+                unsubscribe_url: None,
+            })
+            .collect();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes
+            .iter()
+            .all(|o| o.as_ref().unwrap_err().is_transient()));
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_urls_are_sent_as_list_unsubscribe_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send(&OutgoingEmail {
+                recipient: &recipient,
+                subject: "Subject",
+                html_content: "<p>Content</p>",
+                text_content: "Content",
+                unsubscribe_url: Some("https://example.com/unsubscribe?unsubscribe_token=abc"),
+            })
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body["Headers"],
+            serde_json::json!([
+                {
+                    "Name": "List-Unsubscribe",
+                    "Value": "<https://example.com/unsubscribe?unsubscribe_token=abc>"
+                },
+                {
+                    "Name": "List-Unsubscribe-Post",
+                    "Value": "List-Unsubscribe=One-Click"
+                }
+            ])
+        );
+    }
+}
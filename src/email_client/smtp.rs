@@ -0,0 +1,99 @@
+use anyhow::Context;
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+
+use super::{build_message, EmailError, EmailSender, OutgoingEmail};
+use crate::domain::SubscriberEmail;
+
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpEmailClient {
+    /// `require_tls` upgrades the connection with STARTTLS and refuses to talk to a relay that
+    /// does not support it. Only turn it off for relays running on the local machine.
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<Credentials>,
+        require_tls: bool,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("Failed to set up a TLS connection to the SMTP relay.")?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        let builder = builder.port(port).timeout(Some(timeout));
+        let builder = match credentials {
+            Some(credentials) => builder.credentials(credentials),
+            None => builder,
+        };
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpEmailClient {
+    async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), EmailError> {
+        let message = build_message(&self.sender, email)?;
+        self.transport.send(message).await.map_err(|e| {
+            // 5xx replies reject the message for good, everything else (4xx replies, timeouts,
+            // connection failures) is worth another attempt.
+            if e.is_permanent() {
+                EmailError::Permanent(e.into())
+            } else {
+                EmailError::Transient(e.into())
+            }
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{faker::internet::en::SafeEmail, Fake};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, SmtpEmailClient};
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unreachable_relays_are_a_transient_failure() {
+        // Arrange
+        // Bind a port and release it straight away, so that nobody is listening on it.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let email_client = SmtpEmailClient::new(
+            "127.0.0.1",
+            port,
+            None,
+            false,
+            email(),
+            std::time::Duration::from_millis(200),
+        )
+        .unwrap();
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), "Subject", "<p>Content</p>", "Content")
+            .await;
+
+        // Assert
+        assert!(outcome.unwrap_err().is_transient());
+    }
+}
@@ -0,0 +1,200 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::SubscriberEmail,
+    email_client::{EmailError, EmailSender},
+    issue_delivery_worker::{retry_delay, ExecutionOutcome, MAX_ATTEMPTS},
+    startup::get_connection_pool,
+};
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// Schedules an email for delivery once `transaction` commits.
+///
+/// If the transaction is rolled back the email is never sent, if it commits the email will be
+/// sent even if the email provider is unavailable right now.
+#[tracing::instrument(name = "Add an email to the outbox", skip_all)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            email_id,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        recipient.as_ref(),
+        subject,
+        html_content,
+        text_content,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+pub async fn run_outbox_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client()?;
+    outbox_worker_loop(connection_pool, email_client).await
+}
+
+async fn outbox_worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_dispatch_email(&pool, email_client.as_ref()).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(email_id=tracing::field::Empty, recipient=tracing::field::Empty),
+    err
+)]
+pub async fn try_dispatch_email(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let email = dequeue_email(pool).await?;
+    if email.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (mut transaction, email) = email.unwrap();
+    Span::current()
+        .record("email_id", display(email.email_id))
+        .record("recipient", display(&email.recipient));
+    let outcome = match SubscriberEmail::parse(email.recipient.clone()) {
+        Ok(recipient) => {
+            email_client
+                .send_email(
+                    &recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                )
+                .await
+        }
+        Err(e) => Err(EmailError::Permanent(anyhow::anyhow!(
+            "The recipient address is invalid: {}",
+            e
+        ))),
+    };
+    let n_attempts = email.n_retries + 1;
+    match outcome {
+        Ok(()) => delete_email(&mut transaction, email.email_id).await?,
+        Err(e) if e.is_transient() && n_attempts < MAX_ATTEMPTS => {
+            let delay = retry_delay(email.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a transactional email. Retrying in {:?}.",
+                delay
+            );
+            schedule_retry(&mut transaction, email.email_id, delay).await?;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a transactional email. Giving up after {} attempt(s).",
+                n_attempts
+            );
+            delete_email(&mut transaction, email.email_id).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct OutboxEmail {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    html_content: String,
+    text_content: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_email(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, OutboxEmail)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let email = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        SELECT email_id, recipient, subject, html_content, text_content, n_retries
+        FROM email_outbox
+        WHERE execute_after <= now()
+        ORDER BY created_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(email.map(|email| (transaction, email)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    transaction: &mut PgTransaction,
+    email_id: Uuid,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $2
+        WHERE email_id = $1
+        "#,
+        email_id,
+        execute_after
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_email(
+    transaction: &mut PgTransaction,
+    email_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM email_outbox WHERE email_id = $1"#, email_id)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! The emails we send, rendered from the templates in `templates/emails`.
+//!
+//! Every email comes in two variants, HTML and plain text, sharing the same fields. Templates
+//! are compiled into the binary and checked against their structs at build time: a typo in a
+//! variable name is a compilation error, not a broken email. HTML templates escape every
+//! variable unless it is explicitly marked as `safe`.
+use std::ops::Deref;
+
+use askama::Template;
+
+/// The two bodies of an email, ready to be handed over to an `EmailSender`.
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+pub trait EmailTemplate {
+    fn render_html(&self) -> Result<String, askama::Error>;
+    fn render_text(&self) -> Result<String, askama::Error>;
+
+    fn render(&self) -> Result<RenderedEmail, askama::Error> {
+        Ok(RenderedEmail {
+            html: self.render_html()?,
+            text: self.render_text()?,
+        })
+    }
+}
+
+/// Pairs an HTML template with its plain text variant, which sees the same fields through
+/// `Deref`.
+macro_rules! email_template {
+    ($html:ident, $text:ident, $text_path:literal) => {
+        #[derive(Template)]
+        #[template(path = $text_path)]
+        struct $text<'a, 'b>(&'b $html<'a>);
+
+        impl<'a, 'b> Deref for $text<'a, 'b> {
+            type Target = $html<'a>;
+
+            fn deref(&self) -> &Self::Target {
+                self.0
+            }
+        }
+
+        impl EmailTemplate for $html<'_> {
+            fn render_html(&self) -> Result<String, askama::Error> {
+                Template::render(self)
+            }
+
+            fn render_text(&self) -> Result<String, askama::Error> {
+                $text(self).render()
+            }
+        }
+    };
+}
+
+#[derive(Template)]
+#[template(path = "emails/confirmation.html")]
+pub struct ConfirmationEmail<'a> {
+    pub name: &'a str,
+    pub confirmation_link: &'a str,
+}
+
+email_template!(
+    ConfirmationEmail,
+    ConfirmationEmailText,
+    "emails/confirmation.txt"
+);
+
+/// A newsletter issue, as received by a single subscriber.
+///
+/// The content of the issue is written by an admin and inserted as is.
+#[derive(Template)]
+#[template(path = "emails/newsletter_issue.html")]
+pub struct NewsletterIssueEmail<'a> {
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub unsubscribe_url: &'a str,
+}
+
+email_template!(
+    NewsletterIssueEmail,
+    NewsletterIssueEmailText,
+    "emails/newsletter_issue.txt"
+);
+
+/// The verification link for a subscriber's data export or erasure request.
+#[derive(Template)]
+#[template(path = "emails/data_request.html")]
+pub struct DataRequestEmail<'a> {
+    pub name: &'a str,
+    /// What is being requested, e.g. "a copy of your data".
+    pub request: &'a str,
+    pub link: &'a str,
+    pub expires_in_hours: i64,
+}
+
+email_template!(
+    DataRequestEmail,
+    DataRequestEmailText,
+    "emails/data_request.txt"
+);
+
+/// An invitation to become an admin of the newsletter.
+#[derive(Template)]
+#[template(path = "emails/user_invite.html")]
+pub struct UserInviteEmail<'a> {
+    /// The username of the owner who sent the invite.
+    pub inviter: &'a str,
+    pub role: &'a str,
+    pub link: &'a str,
+    pub expires_in_hours: i64,
+}
+
+email_template!(
+    UserInviteEmail,
+    UserInviteEmailText,
+    "emails/user_invite.txt"
+);
+
+/// The link for an admin to choose a new password.
+#[derive(Template)]
+#[template(path = "emails/password_reset.html")]
+pub struct PasswordResetEmail<'a> {
+    pub username: &'a str,
+    pub link: &'a str,
+    pub expires_in_minutes: i64,
+}
+
+email_template!(
+    PasswordResetEmail,
+    PasswordResetEmailText,
+    "emails/password_reset.txt"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfirmationEmail, EmailTemplate, NewsletterIssueEmail};
+
+    #[test]
+    fn subscriber_details_are_escaped_in_html_emails() {
+        let email = ConfirmationEmail {
+            name: "<script>alert('hi')</script>",
+            confirmation_link: "https://example.com/confirm",
+        }
+        .render()
+        .unwrap();
+
+        assert!(!email.html.contains("<script>"));
+        assert!(email.html.contains("&lt;script&gt;"));
+        // Plain text is never interpreted, there is nothing to escape.
+        assert!(email.text.contains("<script>"));
+    }
+
+    #[test]
+    fn confirmation_emails_contain_the_link_in_both_variants() {
+        let link = "https://example.com/subscriptions/confirm?subscription_token=abc";
+        let email = ConfirmationEmail {
+            name: "Ursula",
+            confirmation_link: link,
+        }
+        .render()
+        .unwrap();
+
+        assert!(email.html.contains(&format!("href=\"{}\"", link)));
+        assert!(email.text.contains(link));
+        assert!(email.text.starts_with("Hi Ursula,"));
+    }
+
+    #[test]
+    fn newsletter_issues_keep_their_html_and_end_with_the_unsubscribe_link() {
+        let unsubscribe_url = "https://example.com/subscriptions/unsubscribe?unsubscribe_token=a";
+        let email = NewsletterIssueEmail {
+            html_content: "<p>Newsletter body as <b>HTML</b></p>",
+            text_content: "Newsletter body as plain text",
+            unsubscribe_url,
+        }
+        .render()
+        .unwrap();
+
+        assert!(email.html.contains("<p>Newsletter body as <b>HTML</b></p>"));
+        assert!(email
+            .html
+            .contains(&format!("<a href=\"{}\">Unsubscribe</a>", unsubscribe_url)));
+        assert_eq!(
+            email.text,
+            format!(
+                "Newsletter body as plain text\n\nUnsubscribe from this newsletter: {}",
+                unsubscribe_url
+            )
+        );
+    }
+}
@@ -0,0 +1,7 @@
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{
+    delete_expired_idempotency_keys, get_saved_response, save_response, try_processing, NextAction,
+};
@@ -0,0 +1,172 @@
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use chrono::{Duration, Utc};
+use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+/// Saved responses are replayed for this long, then the key can be used again.
+const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// Claims the idempotency key for the current request.
+///
+/// The row is inserted inside a transaction that stays open until [`save_response`] commits it:
+/// a concurrent request carrying the same key blocks on the `INSERT` until then, and gets the
+/// saved response back instead of processing the request a second time.
+#[tracing::instrument(name = "Try processing an idempotent request", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2 AND created_at <= $3
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        expiry_cutoff()
+    )
+    .execute(&mut transaction)
+    .await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+#[tracing::instrument(name = "Get saved response", skip(pool))]
+pub async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(r) = saved_response {
+        let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+        let mut response = HttpResponse::build(status_code);
+        for HeaderPairRecord { name, value } in r.response_headers {
+            response.append_header((name, value));
+        }
+        Ok(Some(response.body(r.response_body)))
+    } else {
+        Ok(None)
+    }
+}
+
+#[tracing::instrument(name = "Save response", skip(transaction, http_response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    // `MessageBody::Error` is not `Send` + `Sync`, therefore it doesn't play nicely with `anyhow`
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = {
+        let mut h = Vec::with_capacity(response_head.headers().len());
+        for (name, value) in response_head.headers().iter() {
+            let name = name.as_str().to_owned();
+            let value = value.as_bytes().to_owned();
+            h.push(HeaderPairRecord { name, value });
+        }
+        h
+    };
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    // We need `.map_into_boxed_body` to go from `HttpResponse<Bytes>` to `HttpResponse<BoxBody>`
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
+
+/// Deletes the saved responses that are too old to be replayed.
+#[tracing::instrument(name = "Delete expired idempotency keys", skip_all, err)]
+pub async fn delete_expired_idempotency_keys(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_expired_keys = sqlx::query!(
+        r#"DELETE FROM idempotency WHERE created_at <= $1"#,
+        expiry_cutoff()
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    tracing::info!(n_expired_keys, "Cleaned up expired idempotency keys.");
+    Ok(n_expired_keys)
+}
+
+fn expiry_cutoff() -> chrono::DateTime<Utc> {
+    Utc::now() - Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS)
+}
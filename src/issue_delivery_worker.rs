@@ -0,0 +1,449 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::{MergeFields, MergeTemplate, SubscriberEmail},
+    email_client::{EmailSender, OutgoingEmail},
+    email_templates::{EmailTemplate, NewsletterIssueEmail},
+    startup::get_connection_pool,
+};
+
+/// Deliveries that keep failing with transient errors are dead-lettered after this many attempts.
+pub(crate) const MAX_ATTEMPTS: i16 = 8;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// How many tasks are dequeued, and handed to the email client, at once.
+const BATCH_SIZE: i64 = 500;
+/// How long a batch of dequeued tasks is hidden from other workers while it is being sent.
+const DELIVERY_LEASE: Duration = Duration::from_secs(10 * 60);
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client()?;
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: String,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&pool, email_client.as_ref(), &base_url).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(n_tasks=tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let tasks = dequeue_tasks(pool).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+
+    // An issue that cannot be loaded fails its own tasks, not the whole batch.
+    let mut issues = HashMap::new();
+    for task in &tasks {
+        if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+            entry.insert(get_issue(pool, task.newsletter_issue_id).await);
+        }
+    }
+    // Every subscriber gets their own copy of the issue, with the merge fields filled in and
+    // ending with their unsubscribe link. Tasks that cannot be prepared are not sent.
+    let mut outcomes: HashMap<_, DeliveryOutcome> = HashMap::new();
+    let mut prepared = Vec::new();
+    for task in &tasks {
+        let issue = match &issues[&task.newsletter_issue_id] {
+            Ok(issue) => issue,
+            Err(e) => {
+                let e = anyhow::anyhow!("Failed to load the newsletter issue: {:?}", e);
+                outcomes.insert(task.key(), DeliveryOutcome::TransientFailure(e));
+                continue;
+            }
+        };
+        let recipient = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(recipient) => recipient,
+            Err(e) => {
+                let e = anyhow::anyhow!(
+                    "The stored contact details of the subscriber are invalid: {}",
+                    e
+                );
+                outcomes.insert(task.key(), DeliveryOutcome::PermanentFailure(e));
+                continue;
+            }
+        };
+        let unsubscribe_url = format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            base_url, task.unsubscribe_token
+        );
+        match PersonalisedContent::new(issue, task, unsubscribe_url) {
+            Ok(content) => prepared.push((task, issue, recipient, content)),
+            Err(e) => {
+                outcomes.insert(task.key(), DeliveryOutcome::PermanentFailure(e));
+            }
+        }
+    }
+
+    let emails: Vec<_> = prepared
+        .iter()
+        .map(|(_, issue, recipient, content)| OutgoingEmail {
+            recipient,
+            subject: &issue.title,
+            html_content: &content.html,
+            text_content: &content.text,
+            unsubscribe_url: Some(&content.unsubscribe_url),
+        })
+        .collect();
+    let send_outcomes = email_client.send_batch(&emails).await;
+    let mut send_outcomes = prepared
+        .iter()
+        .map(|(task, ..)| task.key())
+        .zip(send_outcomes)
+        .collect::<HashMap<_, _>>();
+    for (task, ..) in &prepared {
+        let outcome = match send_outcomes.remove(&task.key()) {
+            Some(Ok(())) => DeliveryOutcome::Delivered,
+            Some(Err(e)) if e.is_transient() => DeliveryOutcome::TransientFailure(e.into()),
+            Some(Err(e)) => DeliveryOutcome::PermanentFailure(e.into()),
+            None => DeliveryOutcome::TransientFailure(anyhow::anyhow!(
+                "The email client did not report an outcome for the task."
+            )),
+        };
+        outcomes.insert(task.key(), outcome);
+    }
+
+    // Each outcome is recorded on its own: the emails that went out must not be sent again
+    // because recording another outcome failed. A task whose outcome could not be recorded is
+    // picked up again once its lease runs out.
+    for task in &tasks {
+        let outcome = outcomes
+            .remove(&task.key())
+            .expect("Every task has an outcome.");
+        let _ = handle_outcome(pool, task, outcome).await;
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+enum DeliveryOutcome {
+    Delivered,
+    TransientFailure(anyhow::Error),
+    PermanentFailure(anyhow::Error),
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=%task.newsletter_issue_id,
+        subscriber_email=%task.subscriber_email
+    ),
+    err
+)]
+async fn handle_outcome(
+    pool: &PgPool,
+    task: &Task,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_attempts = task.n_retries + 1;
+    match outcome {
+        DeliveryOutcome::Delivered => delete_task(&mut transaction, task).await?,
+        DeliveryOutcome::TransientFailure(e) if n_attempts < MAX_ATTEMPTS => {
+            let delay = retry_delay(task.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Retrying in {:?}.",
+                delay
+            );
+            schedule_retry(&mut transaction, task, delay).await?;
+        }
+        DeliveryOutcome::TransientFailure(e) | DeliveryOutcome::PermanentFailure(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Moving it to the dead letters.",
+            );
+            dead_letter_task(&mut transaction, task, n_attempts, &e).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// How long to wait before the next attempt, given how many retries already happened.
+///
+/// The delay doubles at every retry, up to `MAX_RETRY_DELAY`, and half of it is randomised so that
+/// tasks which failed together (e.g. during a provider outage) do not all retry at once.
+pub(crate) fn retry_delay(n_retries: i16) -> Duration {
+    let exponent = n_retries.clamp(0, 16) as u32;
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_DELAY);
+    let half = delay / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+    unsubscribe_token: String,
+    subscriber_name: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl Task {
+    fn key(&self) -> (Uuid, &str) {
+        (self.newsletter_issue_id, &self.subscriber_email)
+    }
+}
+
+/// Claims a batch of tasks by pushing their `execute_after` past the lease: other workers skip
+/// them while they are being sent, and they come back to the queue if their outcome never gets
+/// recorded, e.g. because the worker crashed.
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(pool: &PgPool) -> Result<Vec<Task>, anyhow::Error> {
+    let lease_expires_at = Utc::now() + chrono::Duration::from_std(DELIVERY_LEASE)?;
+    // `SKIP LOCKED` lets several workers claim tasks concurrently without ever picking up the
+    // same task twice.
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"
+        WITH claimed AS (
+            SELECT
+                q.newsletter_issue_id,
+                q.subscriber_email,
+                s.unsubscribe_token,
+                s.name AS subscriber_name,
+                s.subscribed_at
+            FROM issue_delivery_queue q
+            JOIN subscriptions s ON s.email = q.subscriber_email
+            WHERE q.execute_after <= now()
+            FOR UPDATE OF q
+            SKIP LOCKED
+            LIMIT $1
+        )
+        UPDATE issue_delivery_queue q
+        SET execute_after = $2
+        FROM claimed c
+        WHERE
+            q.newsletter_issue_id = c.newsletter_issue_id AND
+            q.subscriber_email = c.subscriber_email
+        RETURNING
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            c.unsubscribe_token AS "unsubscribe_token!",
+            c.subscriber_name AS "subscriber_name!",
+            c.subscribed_at AS "subscribed_at!"
+        "#,
+        BATCH_SIZE,
+        lease_expires_at
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(tasks)
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Nothing is kept for a subscriber erased while the task was being sent: the erasure locks
+/// their subscription before deleting their dead letters, and `FOR SHARE` waits for it.
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    n_attempts: i16,
+    error: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            last_error,
+            failed_at
+        )
+        SELECT $1, $2, $3, $4, $5
+        WHERE EXISTS (SELECT 1 FROM subscriptions WHERE email = $2 FOR SHARE)
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_attempts = EXCLUDED.n_attempts,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts,
+        error.to_string(),
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    delete_task(transaction, task).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(transaction: &mut PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct PersonalisedContent {
+    html: String,
+    text: String,
+    unsubscribe_url: String,
+}
+
+impl PersonalisedContent {
+    fn new(
+        issue: &NewsletterIssue,
+        task: &Task,
+        unsubscribe_url: String,
+    ) -> Result<Self, anyhow::Error> {
+        let fields = MergeFields {
+            name: &task.subscriber_name,
+            subscribed_at: task.subscribed_at,
+            unsubscribe_url: &unsubscribe_url,
+        };
+        let email = NewsletterIssueEmail {
+            html_content: &issue.html_content.render_html(&fields),
+            text_content: &issue.text_content.render_text(&fields),
+            unsubscribe_url: &unsubscribe_url,
+        }
+        .render()
+        .context("Failed to render a newsletter issue.")?;
+        Ok(Self {
+            html: email.html,
+            text: email.text,
+            unsubscribe_url,
+        })
+    }
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: MergeTemplate,
+    html_content: MergeTemplate,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content, has_merge_fields
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    if !issue.has_merge_fields {
+        return Ok(NewsletterIssue {
+            title: issue.title,
+            text_content: MergeTemplate::literal(issue.text_content),
+            html_content: MergeTemplate::literal(issue.html_content),
+        });
+    }
+    // Merge fields are validated when the issue is published.
+    Ok(NewsletterIssue {
+        title: issue.title,
+        text_content: MergeTemplate::parse(issue.text_content).map_err(anyhow::Error::msg)?,
+        html_content: MergeTemplate::parse(issue.html_content).map_err(anyhow::Error::msg)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, BASE_RETRY_DELAY, MAX_RETRY_DELAY};
+
+    #[test]
+    fn the_retry_delay_doubles_at_every_retry() {
+        for n_retries in 0..4 {
+            let expected = BASE_RETRY_DELAY * 2u32.pow(n_retries as u32);
+            let delay = retry_delay(n_retries);
+            assert!(delay >= expected / 2 && delay <= expected, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn the_retry_delay_is_capped() {
+        let delay = retry_delay(i16::MAX);
+        assert!(delay >= MAX_RETRY_DELAY / 2 && delay <= MAX_RETRY_DELAY);
+    }
+}
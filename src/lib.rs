@@ -2,8 +2,15 @@ pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod login_throttle;
 pub mod routes;
 pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod subscription_cleanup_worker;
 pub mod telemetry;
 pub mod utils;
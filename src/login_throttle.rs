@@ -0,0 +1,724 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+use crate::configuration::LoginThrottleSettings;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginAttemptStoreKind {
+    #[default]
+    Postgres,
+    Memory,
+}
+
+/// Where failed login attempts are recorded.
+///
+/// Production deployments keep them in Postgres, so that every instance of the application sees
+/// the same attempts. The in-process store is meant for tests and local experiments.
+#[derive(Clone)]
+pub enum LoginAttemptStore {
+    Postgres(PgPool),
+    Memory(MemoryLoginAttemptStore),
+}
+
+impl LoginAttemptStore {
+    pub fn build(kind: LoginAttemptStoreKind, pool: &PgPool) -> Self {
+        match kind {
+            LoginAttemptStoreKind::Postgres => Self::Postgres(pool.clone()),
+            LoginAttemptStoreKind::Memory => Self::Memory(MemoryLoginAttemptStore::default()),
+        }
+    }
+
+    /// Records an attempt under every key, unless `assess` throttles it given the failures of
+    /// each key since `since`. Older failures are ignored, `delete_old_login_attempts` prunes them.
+    ///
+    /// Checking and recording happen atomically: concurrent attempts under the same key cannot
+    /// all get past the check before any of them is recorded.
+    async fn reserve(
+        &self,
+        keys: &[String],
+        at: DateTime<Utc>,
+        since: DateTime<Utc>,
+        assess: impl Fn(&[Vec<DateTime<Utc>>]) -> Option<Throttled>,
+    ) -> Result<Option<Throttled>, anyhow::Error> {
+        match self {
+            Self::Postgres(pool) => {
+                let mut transaction = pool
+                    .begin()
+                    .await
+                    .context("Failed to acquire a Postgres connection from the pool")?;
+                // Locked in a consistent order, two attempts sharing a key cannot deadlock.
+                let mut locked_keys = keys.to_vec();
+                locked_keys.sort();
+                for key in &locked_keys {
+                    sqlx::query!(r#"SELECT pg_advisory_xact_lock(hashtext($1))"#, key)
+                        .execute(&mut transaction)
+                        .await
+                        .context("Failed to lock the failed login attempts.")?;
+                }
+                let rows = sqlx::query!(
+                    r#"
+                    SELECT throttle_key, attempted_at
+                    FROM login_attempts
+                    WHERE throttle_key = ANY($1) AND attempted_at > $2
+                    "#,
+                    keys,
+                    since
+                )
+                .fetch_all(&mut transaction)
+                .await
+                .context("Failed to retrieve the recent failed login attempts.")?;
+                let failures: Vec<Vec<_>> = keys
+                    .iter()
+                    .map(|key| {
+                        rows.iter()
+                            .filter(|row| &row.throttle_key == key)
+                            .map(|row| row.attempted_at)
+                            .collect()
+                    })
+                    .collect();
+                if let Some(throttled) = assess(&failures) {
+                    return Ok(Some(throttled));
+                }
+                sqlx::query!(
+                    r#"
+                    INSERT INTO login_attempts (throttle_key, attempted_at)
+                    SELECT throttle_key, $2 FROM UNNEST($1::text[]) AS throttle_key
+                    "#,
+                    keys,
+                    at
+                )
+                .execute(&mut transaction)
+                .await
+                .context("Failed to record a login attempt.")?;
+                transaction
+                    .commit()
+                    .await
+                    .context("Failed to commit SQL transaction to record a login attempt.")?;
+                Ok(None)
+            }
+            Self::Memory(store) => Ok(store.reserve(keys, at, since, assess)),
+        }
+    }
+
+    /// Removes a single attempt recorded at `at` under every key.
+    async fn release(&self, keys: &[String], at: DateTime<Utc>) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(pool) => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM login_attempts
+                    WHERE ctid IN (
+                        SELECT DISTINCT ON (throttle_key) ctid
+                        FROM login_attempts
+                        WHERE throttle_key = ANY($1) AND attempted_at = $2
+                    )
+                    "#,
+                    keys,
+                    at
+                )
+                .execute(pool)
+                .await
+                .context("Failed to release a login attempt.")?;
+                Ok(())
+            }
+            Self::Memory(store) => {
+                store.release(keys, at);
+                Ok(())
+            }
+        }
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(pool) => {
+                sqlx::query!(r#"DELETE FROM login_attempts WHERE throttle_key = $1"#, key)
+                    .execute(pool)
+                    .await
+                    .context("Failed to clear the failed login attempts.")?;
+                Ok(())
+            }
+            Self::Memory(store) => {
+                store.attempts.lock().unwrap().remove(key);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An in-process store of failed login attempts, shared by every worker of the same
+/// `HttpServer`.
+///
+/// Attempts are lost when the process exits: do not use it in production.
+#[derive(Clone, Default)]
+pub struct MemoryLoginAttemptStore {
+    attempts: Arc<Mutex<HashMap<String, Vec<DateTime<Utc>>>>>,
+}
+
+impl MemoryLoginAttemptStore {
+    fn reserve(
+        &self,
+        keys: &[String],
+        at: DateTime<Utc>,
+        since: DateTime<Utc>,
+        assess: impl Fn(&[Vec<DateTime<Utc>>]) -> Option<Throttled>,
+    ) -> Option<Throttled> {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, failures| {
+            failures.retain(|failure| *failure > since);
+            !failures.is_empty()
+        });
+        let failures: Vec<_> = keys
+            .iter()
+            .map(|key| attempts.get(key).cloned().unwrap_or_default())
+            .collect();
+        if let Some(throttled) = assess(&failures) {
+            return Some(throttled);
+        }
+        for key in keys {
+            attempts.entry(key.clone()).or_default().push(at);
+        }
+        None
+    }
+
+    fn release(&self, keys: &[String], at: DateTime<Utc>) {
+        let mut attempts = self.attempts.lock().unwrap();
+        for key in keys {
+            if let Some(failures) = attempts.get_mut(key) {
+                if let Some(i) = failures.iter().position(|failure| *failure == at) {
+                    failures.remove(i);
+                }
+            }
+        }
+    }
+}
+
+/// Why a login attempt was turned down before checking the credentials.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Throttled {
+    pub retry_after: Duration,
+    /// `false` while failures only slow further attempts down.
+    pub locked_out: bool,
+}
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.locked_out {
+            let minutes = (self.retry_after_seconds() + 59) / 60;
+            write!(
+                f,
+                "Too many failed login attempts. Logins are locked for {} minute(s).",
+                minutes
+            )
+        } else {
+            write!(
+                f,
+                "Too many failed login attempts. Please wait {} second(s) before trying again.",
+                self.retry_after_seconds()
+            )
+        }
+    }
+}
+
+impl Throttled {
+    /// Rounded up, for the `Retry-After` header.
+    pub fn retry_after_seconds(&self) -> i64 {
+        let seconds = self.retry_after.num_seconds();
+        if self.retry_after > Duration::seconds(seconds) {
+            seconds + 1
+        } else {
+            seconds
+        }
+    }
+}
+
+/// Slows down, then locks out, logins after repeated failures, both per username and per client
+/// address. Throttled attempts are turned down before the password hash is computed: they cost
+/// next to nothing, and they are not recorded as failures.
+///
+/// Each username gets a few free attempts, then has to wait longer and longer between attempts
+/// until it gets locked out. Client addresses are only locked out, with a higher limit: many
+/// users can share one, e.g. behind a NAT.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    store: LoginAttemptStore,
+    settings: LoginThrottleSettings,
+}
+
+/// When failures start slowing attempts down and locking them out.
+struct Limits {
+    delay_after: Option<u32>,
+    lockout_after: u32,
+}
+
+/// An attempt that got past the throttle. It is recorded as a failure up front, so that
+/// concurrent attempts see it while the credentials are being checked, and stays one unless it
+/// is released or succeeds.
+#[derive(Debug)]
+pub struct LoginAttempt {
+    username: String,
+    keys: Vec<String>,
+    at: DateTime<Utc>,
+}
+
+impl LoginThrottle {
+    pub fn new(store: LoginAttemptStore, settings: LoginThrottleSettings) -> Self {
+        Self { store, settings }
+    }
+
+    /// Records an attempt for `username`, from `client_address`, unless it is throttled.
+    #[tracing::instrument(name = "Reserve a login attempt", skip(self))]
+    pub async fn reserve_attempt(
+        &self,
+        username: &str,
+        client_address: Option<&str>,
+    ) -> Result<Result<LoginAttempt, Throttled>, anyhow::Error> {
+        let mut keys = vec![username_key(username)];
+        keys.extend(client_address.map(address_key));
+        self.reserve(username, keys).await
+    }
+
+    /// Records a request for a password reset link, unless it is throttled like a login.
+    ///
+    /// Requests are counted apart from logins: asking for links cannot lock a user out of
+    /// logging in, and is throttled whether or not the username exists.
+    #[tracing::instrument(name = "Reserve a password reset request", skip(self))]
+    pub async fn reserve_reset_request(
+        &self,
+        username: &str,
+        client_address: Option<&str>,
+    ) -> Result<Result<LoginAttempt, Throttled>, anyhow::Error> {
+        let mut keys = vec![reset_key(&username_key(username))];
+        keys.extend(client_address.map(|address| reset_key(&address_key(address))));
+        self.reserve(username, keys).await
+    }
+
+    async fn reserve(
+        &self,
+        username: &str,
+        keys: Vec<String>,
+    ) -> Result<Result<LoginAttempt, Throttled>, anyhow::Error> {
+        let now = Utc::now();
+        let username_limits = Limits {
+            delay_after: Some(self.settings.free_attempts),
+            lockout_after: self.settings.max_attempts_per_username,
+        };
+        let address_limits = Limits {
+            delay_after: None,
+            lockout_after: self.settings.max_attempts_per_address,
+        };
+        let assess = |failures: &[Vec<DateTime<Utc>>]| {
+            let limits = [&username_limits, &address_limits];
+            // The key that keeps the client waiting the longest wins.
+            failures
+                .iter()
+                .zip(limits)
+                .filter_map(|(failures, limits)| self.assess(failures, now, limits))
+                .max_by_key(|throttled| throttled.retry_after)
+        };
+        let throttled = self
+            .store
+            .reserve(&keys, now, now - self.window(), assess)
+            .await?;
+        Ok(match throttled {
+            Some(throttled) => Err(throttled),
+            None => Ok(LoginAttempt {
+                username: username.to_owned(),
+                keys,
+                at: now,
+            }),
+        })
+    }
+
+    /// Takes back an attempt that did not fail, but did not log the user in either: e.g. the
+    /// password is right and the second factor is still to be checked.
+    #[tracing::instrument(name = "Release a login attempt", skip(self))]
+    pub async fn release(&self, attempt: LoginAttempt) -> Result<(), anyhow::Error> {
+        self.store.release(&attempt.keys, attempt.at).await
+    }
+
+    /// Forgets the failures of a username once it has logged in. Those of the client address
+    /// are kept: one valid account must not be enough to try out passwords for the others.
+    #[tracing::instrument(name = "Reset the login throttle", skip(self))]
+    pub async fn record_success(&self, attempt: LoginAttempt) -> Result<(), anyhow::Error> {
+        self.store.release(&attempt.keys, attempt.at).await?;
+        self.store.clear(&username_key(&attempt.username)).await
+    }
+
+    /// The address of the client. Forwarding headers can be forged by anyone reaching the
+    /// application directly, they are only trusted when set by one of the configured proxies.
+    pub fn client_address(&self, request: &HttpRequest) -> Option<String> {
+        let peer_address = request.peer_addr()?.ip();
+        let trusted_proxies = &self.settings.trusted_proxies;
+        if !trusted_proxies.contains(&peer_address) {
+            return Some(peer_address.to_string());
+        }
+        // Every proxy appends the address it got the request from: the first untrusted address
+        // from the right is the client, anything further left may have been made up by it.
+        let forwarded_for: Vec<_> = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        let client_address = forwarded_for
+            .into_iter()
+            .rev()
+            .map_while(|address| address.trim().parse::<IpAddr>().ok())
+            .find(|address| !trusted_proxies.contains(address))
+            .unwrap_or(peer_address);
+        Some(client_address.to_string())
+    }
+
+    fn window(&self) -> Duration {
+        Duration::seconds(self.settings.window_seconds as i64)
+    }
+
+    /// Decides from the failures within the window when the next attempt can be made.
+    fn assess(
+        &self,
+        failures: &[DateTime<Utc>],
+        now: DateTime<Utc>,
+        limits: &Limits,
+    ) -> Option<Throttled> {
+        let last_failure = *failures.iter().max()?;
+        let n_failures = failures.len() as u32;
+        let (retry_at, locked_out) = if n_failures >= limits.lockout_after {
+            let lockout = Duration::seconds(self.settings.lockout_seconds as i64);
+            (last_failure + lockout, true)
+        } else if let Some(delay_after) = limits.delay_after.filter(|d| n_failures >= *d) {
+            // Doubles with every failure past the free ones.
+            let exponent = (n_failures - delay_after).min(31);
+            let delay = self
+                .settings
+                .base_delay_seconds
+                .saturating_mul(1 << exponent)
+                .min(self.settings.max_delay_seconds);
+            (last_failure + Duration::seconds(delay as i64), false)
+        } else {
+            return None;
+        };
+        (retry_at > now).then(|| Throttled {
+            retry_after: retry_at - now,
+            locked_out,
+        })
+    }
+}
+
+/// Deletes the failed login attempts that have fallen out of the throttling window.
+#[tracing::instrument(name = "Delete old login attempts", skip_all, err)]
+pub async fn delete_old_login_attempts(
+    pool: &PgPool,
+    settings: &LoginThrottleSettings,
+) -> Result<u64, anyhow::Error> {
+    let since = Utc::now() - Duration::seconds(settings.window_seconds as i64);
+    let n_old_login_attempts = sqlx::query!(
+        r#"DELETE FROM login_attempts WHERE attempted_at <= $1"#,
+        since
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete the old failed login attempts.")?
+    .rows_affected();
+    tracing::info!(n_old_login_attempts, "Cleaned up old login attempts.");
+    Ok(n_old_login_attempts)
+}
+
+fn username_key(username: &str) -> String {
+    format!("username:{}", username)
+}
+
+fn address_key(client_address: &str) -> String {
+    format!("ip:{}", client_address)
+}
+
+fn reset_key(key: &str) -> String {
+    format!("reset:{}", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use actix_web::test::TestRequest;
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::{
+        Limits, LoginAttemptStore, LoginAttemptStoreKind, LoginThrottle, MemoryLoginAttemptStore,
+        Throttled,
+    };
+    use crate::configuration::LoginThrottleSettings;
+
+    fn settings() -> LoginThrottleSettings {
+        LoginThrottleSettings {
+            store: LoginAttemptStoreKind::Memory,
+            window_seconds: 900,
+            free_attempts: 3,
+            base_delay_seconds: 2,
+            max_delay_seconds: 60,
+            max_attempts_per_username: 10,
+            max_attempts_per_address: 100,
+            lockout_seconds: 900,
+            trusted_proxies: vec![],
+        }
+    }
+
+    /// A throttle with the default settings, and a handle on the attempts it records.
+    fn memory_throttle() -> (LoginThrottle, MemoryLoginAttemptStore) {
+        let store = MemoryLoginAttemptStore::default();
+        let throttle = LoginThrottle::new(LoginAttemptStore::Memory(store.clone()), settings());
+        (throttle, store)
+    }
+
+    const USERNAME_LIMITS: Limits = Limits {
+        delay_after: Some(3),
+        lockout_after: 10,
+    };
+
+    fn failures(n: usize, at: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        vec![at; n]
+    }
+
+    fn n_attempts(store: &MemoryLoginAttemptStore, key: &str) -> usize {
+        store.attempts.lock().unwrap().get(key).map_or(0, Vec::len)
+    }
+
+    #[test]
+    fn the_first_attempts_are_free() {
+        let (throttle, _) = memory_throttle();
+        let now = Utc::now();
+        assert_eq!(
+            throttle.assess(&failures(2, now), now, &USERNAME_LIMITS),
+            None
+        );
+    }
+
+    #[test]
+    fn delays_double_with_every_failure_up_to_the_maximum() {
+        let (throttle, _) = memory_throttle();
+        let now = Utc::now();
+        for (n_failures, expected_delay) in [(3, 2), (4, 4), (5, 8), (8, 60), (9, 60)] {
+            assert_eq!(
+                throttle.assess(&failures(n_failures, now), now, &USERNAME_LIMITS),
+                Some(Throttled {
+                    retry_after: Duration::seconds(expected_delay),
+                    locked_out: false
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn delays_and_lockouts_run_from_the_last_failure() {
+        let (throttle, _) = memory_throttle();
+        let now = Utc::now();
+        assert_eq!(
+            throttle.assess(
+                &failures(3, now - Duration::seconds(5)),
+                now,
+                &USERNAME_LIMITS
+            ),
+            None
+        );
+        let throttled = throttle
+            .assess(
+                &failures(10, now - Duration::seconds(300)),
+                now,
+                &USERNAME_LIMITS,
+            )
+            .unwrap();
+        assert!(throttled.locked_out);
+        assert_eq!(throttled.retry_after_seconds(), 600);
+        assert_eq!(
+            throttled.to_string(),
+            "Too many failed login attempts. Logins are locked for 10 minute(s)."
+        );
+    }
+
+    #[tokio::test]
+    async fn attempts_count_as_failures_until_they_succeed() {
+        let (throttle, store) = memory_throttle();
+        for _ in 0..3 {
+            throttle
+                .reserve_attempt("ursula", Some("192.0.2.1"))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        let throttled = throttle
+            .reserve_attempt("ursula", Some("192.0.2.1"))
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert!(!throttled.locked_out);
+        // Throttled attempts are not recorded.
+        assert_eq!(n_attempts(&store, "username:ursula"), 3);
+    }
+
+    #[tokio::test]
+    async fn failures_outside_the_window_are_forgotten() {
+        let (throttle, store) = memory_throttle();
+        let long_ago = Utc::now() - Duration::seconds(901);
+        store
+            .attempts
+            .lock()
+            .unwrap()
+            .insert("username:ursula".into(), failures(10, long_ago));
+
+        let attempt = throttle.reserve_attempt("ursula", None).await.unwrap();
+
+        assert!(attempt.is_ok());
+        assert_eq!(n_attempts(&store, "username:ursula"), 1);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_locked_out_but_not_slowed_down() {
+        let (throttle, _) = memory_throttle();
+        for i in 0..99 {
+            throttle
+                .reserve_attempt(&format!("user-{}", i), Some("192.0.2.1"))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let attempt = throttle
+            .reserve_attempt("user-99", Some("192.0.2.1"))
+            .await
+            .unwrap();
+        assert!(attempt.is_ok());
+
+        let throttled = throttle
+            .reserve_attempt("someone-else", Some("192.0.2.1"))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(throttled.locked_out);
+        assert!(throttle
+            .reserve_attempt("someone-else", Some("192.0.2.2"))
+            .await
+            .unwrap()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_success_resets_the_username_and_takes_back_its_own_attempt() {
+        let (throttle, store) = memory_throttle();
+        for _ in 0..2 {
+            throttle
+                .reserve_attempt("ursula", Some("192.0.2.1"))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let attempt = throttle
+            .reserve_attempt("ursula", Some("192.0.2.1"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        throttle.record_success(attempt).await.unwrap();
+
+        assert_eq!(n_attempts(&store, "username:ursula"), 0);
+        assert_eq!(n_attempts(&store, "ip:192.0.2.1"), 2);
+    }
+
+    #[tokio::test]
+    async fn a_released_attempt_is_not_a_failure() {
+        let (throttle, store) = memory_throttle();
+        throttle
+            .reserve_attempt("ursula", Some("192.0.2.1"))
+            .await
+            .unwrap()
+            .unwrap();
+        let attempt = throttle
+            .reserve_attempt("ursula", Some("192.0.2.1"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        throttle.release(attempt).await.unwrap();
+
+        assert_eq!(n_attempts(&store, "username:ursula"), 1);
+        assert_eq!(n_attempts(&store, "ip:192.0.2.1"), 1);
+    }
+
+    #[tokio::test]
+    async fn reset_requests_are_counted_apart_from_logins() {
+        let (throttle, store) = memory_throttle();
+        for _ in 0..3 {
+            throttle
+                .reserve_reset_request("ursula", Some("192.0.2.1"))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        assert!(throttle
+            .reserve_reset_request("ursula", Some("192.0.2.1"))
+            .await
+            .unwrap()
+            .is_err());
+        assert!(throttle
+            .reserve_attempt("ursula", Some("192.0.2.1"))
+            .await
+            .unwrap()
+            .is_ok());
+        assert_eq!(n_attempts(&store, "reset:username:ursula"), 3);
+        assert_eq!(n_attempts(&store, "reset:ip:192.0.2.1"), 3);
+        assert_eq!(n_attempts(&store, "username:ursula"), 1);
+    }
+
+    fn request_from(peer: &str, forwarded_for: Option<&str>) -> actix_web::HttpRequest {
+        let mut request =
+            TestRequest::default().peer_addr(SocketAddr::new(peer.parse().unwrap(), 41234));
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.insert_header(("X-Forwarded-For", forwarded_for));
+        }
+        request.to_http_request()
+    }
+
+    #[test]
+    fn forwarding_headers_are_ignored_without_a_trusted_proxy() {
+        let (throttle, _) = memory_throttle();
+        let request = request_from("192.0.2.1", Some("203.0.113.7"));
+        assert_eq!(
+            throttle.client_address(&request).as_deref(),
+            Some("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_report_the_client_address() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let settings = LoginThrottleSettings {
+            trusted_proxies: vec![proxy],
+            ..settings()
+        };
+        let throttle = LoginThrottle::new(
+            LoginAttemptStore::Memory(MemoryLoginAttemptStore::default()),
+            settings,
+        );
+
+        // The client made up the first address, the proxy appended the one it saw.
+        let request = request_from("10.0.0.1", Some("203.0.113.7, 198.51.100.2"));
+        assert_eq!(
+            throttle.client_address(&request).as_deref(),
+            Some("198.51.100.2")
+        );
+        let request = request_from("10.0.0.1", None);
+        assert_eq!(
+            throttle.client_address(&request).as_deref(),
+            Some("10.0.0.1")
+        );
+    }
+}
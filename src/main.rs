@@ -1,9 +1,15 @@
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
 use zero2prod::configuration::get_configuration;
+use zero2prod::email_outbox::run_outbox_worker_until_stopped;
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
 use zero2prod::startup::Application;
+use zero2prod::subscription_cleanup_worker::run_cleanup_until_stopped;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     // trace -> debug -> info -> warn -> error // log level severtity
     // If no RUST_LOG environment variable has been set the value will default to `info`
     let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
@@ -13,8 +19,44 @@ async fn main() -> std::io::Result<()> {
     // get the configuration needed from file.
     let configuration = get_configuration().expect("Failed to read configuration");
 
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    // The background workers run alongside the HTTP server, if any of them exits the whole
+    // process shuts down.
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let outbox_task = tokio::spawn(run_outbox_worker_until_stopped(configuration.clone()));
+    let cleanup_task = tokio::spawn(run_cleanup_until_stopped(configuration));
+
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+        o = outbox_task => report_exit("Outbox worker", o),
+        o = cleanup_task => report_exit("Subscription cleanup worker", o),
+    };
 
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
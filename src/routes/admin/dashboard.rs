@@ -1,25 +1,45 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
-use reqwest::header::LOCATION;
 use sqlx::PgPool;
+use std::fmt::Write;
 use uuid::Uuid;
 
-use crate::{session_state::TypedSession, utils::e500};
+use crate::{
+    authentication::{Role, UserId},
+    utils::{e500, escape_html},
+};
 
-#[actix_web::get("/admin/dashboard")]
+#[actix_web::get("/dashboard")]
 pub async fn admin_dashboard(
-    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
     pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
-        get_username(user_id, &pool).await.map_err(e500)?
-    } else {
-        return Ok(HttpResponse::SeeOther()
-            .insert_header((LOCATION, "/login"))
-            .finish());
-    };
-    Ok(HttpResponse::Ok().body(format!(
-        r#"
+    let username = get_username(*user_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?;
+    // Usernames picked before they were restricted to safe characters may contain markup.
+    let username = escape_html(&username);
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    let role = role.into_inner();
+    // Links to pages the user is not allowed to see are left out, the pages reject them anyway.
+    let mut restricted_actions = String::new();
+    if role >= Role::Editor {
+        restricted_actions
+            .push_str(r#"<li><a href="/admin/newsletters">Send a newsletter issue</a></li>"#);
+    }
+    if role >= Role::Owner {
+        restricted_actions.push_str(r#"<li><a href="/admin/users">Users</a></li>"#);
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
 <!DOCTYPE html>
     <html lang="en">
     <head>
@@ -27,18 +47,40 @@ pub async fn admin_dashboard(
         <title>Admin dashboard</title>
     </head>
     <body>
-        <p>Welcome {username}!</p>
+        {msg_html}
+        <p>Welcome {username}! Your role: {role}.</p>
         <p>Available actions:</p>
         <ol>
+            {restricted_actions}
+            <li><a href="/admin/subscribers">Subscribers</a></li>
             <li><a href="/admin/password">Change password</a></li>
+            <li><a href="/admin/two_factor">Two-factor authentication</a></li>
+            <li><a href="/admin/deliveries/failed">Failed deliveries</a></li>
+            <li>
+                Preview emails:
+                <a href="/admin/email_templates/confirmation">confirmation</a>,
+                <a href="/admin/email_templates/newsletter_issue">newsletter issue</a>,
+                <a href="/admin/email_templates/user_invite">user invite</a>,
+                <a href="/admin/email_templates/password_reset">password reset</a>
+            </li>
+            <li>
+                <form name="logoutOthersForm" action="/admin/logout/others" method="post">
+                    <input type="submit" value="Log out all my other sessions">
+                </form>
+            </li>
+            <li>
+                <form name="logoutForm" action="/admin/logout" method="post">
+                    <input type="submit" value="Log out">
+                </form>
+            </li>
         </ol>
     </body>
     </html>
     "#,
-    )))
+        )))
 }
 
-async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
     let row = sqlx::query!(
         r#"
         SELECT username
@@ -0,0 +1,107 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::{e500, escape_html};
+
+#[actix_web::get("/deliveries/failed")]
+pub async fn failed_deliveries(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    let mut rows_html = String::new();
+    for dead_letter in get_dead_letters(&pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{title}</td>
+                <td>{email}</td>
+                <td>{n_attempts}</td>
+                <td>{last_error}</td>
+                <td>{failed_at}</td>
+                <td>
+                    <form action="/admin/deliveries/failed/requeue" method="post">
+                        <input type="hidden" name="newsletter_issue_id" value="{issue_id}">
+                        <input type="hidden" name="subscriber_email" value="{email}">
+                        <button type="submit">Requeue</button>
+                    </form>
+                </td>
+            </tr>"#,
+            title = escape_html(&dead_letter.title),
+            email = escape_html(&dead_letter.subscriber_email),
+            n_attempts = dead_letter.n_attempts,
+            last_error = escape_html(&dead_letter.last_error),
+            failed_at = dead_letter.failed_at.to_rfc3339(),
+            issue_id = dead_letter.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Failed deliveries</title>
+    </head>
+    <body>
+        {msg_html}
+        <table>
+            <tr>
+                <th>Issue</th>
+                <th>Subscriber</th>
+                <th>Attempts</th>
+                <th>Last error</th>
+                <th>Failed at</th>
+                <th></th>
+            </tr>
+            {rows_html}
+        </table>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+    </html>
+    "#,
+        )))
+}
+
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_attempts: i16,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get dead-lettered deliveries", skip(pool))]
+async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            i.title,
+            d.subscriber_email,
+            d.n_attempts,
+            d.last_error,
+            d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        ORDER BY d.failed_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the dead-lettered deliveries.")?;
+    Ok(dead_letters)
+}
@@ -0,0 +1,85 @@
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::reject_viewers,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tracing::instrument(name = "Requeue a failed delivery", skip(form, pool))]
+#[post(
+    "/deliveries/failed/requeue",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn requeue_failed_delivery(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued = requeue(&pool, form.newsletter_issue_id, &form.subscriber_email)
+        .await
+        .map_err(e500)?;
+    if requeued {
+        FlashMessage::info(format!(
+            "The delivery to {} has been requeued.",
+            form.subscriber_email
+        ))
+        .send();
+    } else {
+        FlashMessage::error("There is no failed delivery matching your request.").send();
+    }
+    Ok(see_other("/admin/deliveries/failed"))
+}
+
+async fn requeue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_dead_letters
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the dead-lettered delivery.")?
+    .rows_affected();
+    if deleted == 0 {
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enqueue the delivery again.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to requeue a delivery.")?;
+    Ok(true)
+}
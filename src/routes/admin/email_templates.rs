@@ -0,0 +1,91 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+
+use crate::{
+    email_templates::{
+        ConfirmationEmail, EmailTemplate, NewsletterIssueEmail, PasswordResetEmail, UserInviteEmail,
+    },
+    routes::RESET_TOKEN_TTL_MINUTES,
+    startup::ApplicationBaseUrl,
+    utils::e500,
+};
+
+use super::users::INVITE_TTL_HOURS;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateName {
+    Confirmation,
+    NewsletterIssue,
+    UserInvite,
+    PasswordReset,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    #[serde(default)]
+    format: TemplateFormat,
+}
+
+const SAMPLE_SUBSCRIBER_NAME: &str = "Ursula Le Guin";
+const SAMPLE_TOKEN: &str = "sampletoken";
+
+/// Renders one of our email templates against a sample subscriber, exactly as it would be sent.
+#[actix_web::get("/email_templates/{template}")]
+pub async fn preview_email_template(
+    template: web::Path<TemplateName>,
+    parameters: web::Query<PreviewParameters>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url.0, SAMPLE_TOKEN
+    );
+    let unsubscribe_url = format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        base_url.0, SAMPLE_TOKEN
+    );
+    let invite_link = format!(
+        "{}/invites/accept?invite_token={}",
+        base_url.0, SAMPLE_TOKEN
+    );
+    let reset_link = format!("{}/login/reset?reset_token={}", base_url.0, SAMPLE_TOKEN);
+    let template: Box<dyn EmailTemplate> = match template.into_inner() {
+        TemplateName::Confirmation => Box::new(ConfirmationEmail {
+            name: SAMPLE_SUBSCRIBER_NAME,
+            confirmation_link: &confirmation_link,
+        }),
+        TemplateName::NewsletterIssue => Box::new(NewsletterIssueEmail {
+            html_content: "<h1>Sample issue</h1><p>The content of the issue goes here.</p>",
+            text_content: "Sample issue\n\nThe content of the issue goes here.",
+            unsubscribe_url: &unsubscribe_url,
+        }),
+        TemplateName::UserInvite => Box::new(UserInviteEmail {
+            inviter: "admin",
+            role: "editor",
+            link: &invite_link,
+            expires_in_hours: INVITE_TTL_HOURS,
+        }),
+        TemplateName::PasswordReset => Box::new(PasswordResetEmail {
+            username: "admin",
+            link: &reset_link,
+            expires_in_minutes: RESET_TOKEN_TTL_MINUTES,
+        }),
+    };
+    let response = match parameters.0.format {
+        TemplateFormat::Html => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(template.render_html().map_err(e500)?),
+        TemplateFormat::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(template.render_text().map_err(e500)?),
+    };
+    Ok(response)
+}
@@ -0,0 +1,44 @@
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{revoke_other_sessions, revoke_session, UserId},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+#[post("/logout")]
+pub async fn log_out(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_session(session_id, &pool).await.map_err(e500)?;
+    }
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+    Ok(see_other("/login"))
+}
+
+#[post("/logout/others")]
+pub async fn log_out_other_sessions(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // `reject_anonymous_users` only lets requests with a registered session id through.
+    let session_id = session
+        .get_session_id()
+        .map_err(e500)?
+        .ok_or_else(|| e500("The session id is missing."))?;
+    let revoked = revoke_other_sessions(*user_id.into_inner(), session_id, &pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "You have logged out of {} other session(s).",
+        revoked
+    ))
+    .send();
+    Ok(see_other("/admin/dashboard"))
+}
@@ -1,5 +1,19 @@
 mod dashboard;
+mod deliveries;
+mod email_templates;
+mod logout;
+mod newsletters;
 mod password;
+mod subscribers;
+mod two_factor;
+mod users;
 
 pub use dashboard::admin_dashboard;
+pub use deliveries::*;
+pub use email_templates::preview_email_template;
+pub use logout::{log_out, log_out_other_sessions};
+pub use newsletters::*;
 pub use password::*;
+pub use subscribers::*;
+pub use two_factor::*;
+pub use users::*;
@@ -0,0 +1,73 @@
+use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use std::fmt::Write;
+
+use crate::{authentication::reject_viewers, utils::escape_html};
+
+#[actix_web::get(
+    "/newsletters",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn publish_newsletter_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    // A fresh key for every rendering of the form: submitting the same form twice (e.g. a double
+    // click or a retry after a timeout) publishes the issue only once.
+    let idempotency_key = uuid::Uuid::new_v4();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Publish Newsletter Issue</title>
+    </head>
+    <body>
+        {msg_html}
+        <p>
+            Both contents can use merge fields, filled in for every subscriber:
+            <code>{{{{ name }}}}</code>, <code>{{{{ subscribed_at }}}}</code> and
+            <code>{{{{ unsubscribe_url }}}}</code>.
+        </p>
+        <form action="/admin/newsletters" method="post">
+            <label>Title:<br>
+                <input
+                    type="text"
+                    placeholder="Enter the issue title"
+                    name="title"
+                >
+            </label>
+            <br>
+            <label>Plain text content:<br>
+                <textarea
+                    placeholder="Enter the content in plain text"
+                    name="text_content"
+                    rows="20"
+                    cols="50"
+                ></textarea>
+            </label>
+            <br>
+            <label>HTML content:<br>
+                <textarea
+                    placeholder="Enter the content in HTML format"
+                    name="html_content"
+                    rows="20"
+                    cols="50"
+                ></textarea>
+            </label>
+            <br>
+            <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+            <button type="submit">Publish</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+    </html>
+              "#,
+        )))
+}
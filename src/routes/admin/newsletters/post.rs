@@ -0,0 +1,93 @@
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{reject_viewers, UserId},
+    domain::MergeTemplate,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    routes::newsletter::{enqueue_delivery_tasks, insert_newsletter_issue},
+    utils::{e400, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the admin form",
+    skip_all,
+    fields(user_id=%*user_id)
+)]
+#[post(
+    "/newsletters",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn publish_newsletter_issue(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let FormData {
+        title,
+        text_content,
+        html_content,
+        idempotency_key,
+    } = form.0;
+
+    if let Err(e) = validate_issue(&title, &text_content, &html_content) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message().send();
+            return Ok(saved_response);
+        }
+    };
+    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
+        .await
+        .context("Failed to store newsletter issue details")
+        .map_err(e500)?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?;
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message().send();
+    Ok(response)
+}
+
+fn validate_issue(title: &str, text_content: &str, html_content: &str) -> Result<(), String> {
+    if title.trim().is_empty() {
+        return Err("The newsletter issue must have a title.".into());
+    }
+    if text_content.trim().is_empty() {
+        return Err("The plain text content of the issue cannot be empty.".into());
+    }
+    if html_content.trim().is_empty() {
+        return Err("The HTML content of the issue cannot be empty.".into());
+    }
+    MergeTemplate::parse(text_content.into())?;
+    MergeTemplate::parse(html_content.into())?;
+    Ok(())
+}
+
+fn success_message() -> FlashMessage {
+    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
+}
@@ -1,24 +1,29 @@
 use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use std::fmt::Write;
 
-use crate::{
-    session_state::TypedSession,
-    utils::{e500, see_other},
-};
+use crate::utils::escape_html;
 
-#[actix_web::get("/admin/password")]
-pub async fn change_password_form(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
-        return Ok(see_other("/login"));
+#[actix_web::get("/password")]
+pub async fn change_password_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
     }
-    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
-        r#"
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
 <!DOCTYPE html>
-    <html lang-"en">
+    <html lang="en">
     <head>
         <meta http-equiv="content-type" content="text/html; charset=utf-8">
         <title>Change Password</title>
     </head>
     <body>
+        {msg_html}
         <form action="/admin/password" method="post">
             <label>Current password
                 <input
@@ -46,9 +51,9 @@ pub async fn change_password_form(session: TypedSession) -> Result<HttpResponse,
             <br>
             <button type="submit">Change password</button>
         </form>
-        <p><a href="/admin/dashbord">&lt;- Back</a></p>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
     </body>
     </html>
               "#,
-    ))
+        )))
 }
@@ -1,7 +1,15 @@
-use actix_web::{post, web, HttpResponse};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
 use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
 
 use crate::{
+    authentication::{
+        self, revoke_other_sessions, validate_credentials, AuthError, BreachedPasswords,
+        Credentials, NewPassword, PasswordHashing, UserId,
+    },
+    login_throttle::LoginThrottle,
+    routes::admin::dashboard::get_username,
     session_state::TypedSession,
     utils::{e500, see_other},
 };
@@ -13,16 +21,84 @@ pub struct FormData {
     new_password_check: Secret<String>,
 }
 
-#[post("/admin/password")]
+#[allow(clippy::too_many_arguments)]
+#[post("/password")]
 pub async fn change_password(
     form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    breached_passwords: web::Data<BreachedPasswords>,
+    request: HttpRequest,
+    login_throttle: web::Data<LoginThrottle>,
     session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
-        return Ok(see_other("/login"));
-    }
+    let user_id = *user_id.into_inner();
+
     if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.current_password.clone(),
+    };
+    // The current password goes through the login throttle: a hijacked session must not be
+    // enough to guess it.
+    let client_address = login_throttle.client_address(&request);
+    let attempt = match login_throttle
+        .reserve_attempt(&credentials.username, client_address.as_deref())
+        .await
+        .map_err(e500)?
+    {
+        Ok(attempt) => attempt,
+        Err(throttled) => {
+            FlashMessage::error(throttled.to_string()).send();
+            return Ok(see_other("/admin/password"));
+        }
+    };
+    if let Err(e) = validate_credentials(credentials, &hashing, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+    login_throttle.record_success(attempt).await.map_err(e500)?;
+
+    // The current password has been verified above, so comparing against it here does not leak
+    // anything the user does not already know.
+    if form.new_password.expose_secret() == form.current_password.expose_secret() {
+        FlashMessage::error("The new password must be different from the current one.").send();
         return Ok(see_other("/admin/password"));
     }
-    todo!()
+    let new_password = match NewPassword::parse(form.0.new_password, &breached_passwords) {
+        Ok(new_password) => new_password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/password"));
+        }
+    };
+
+    authentication::change_password(user_id, new_password.into(), &hashing, &pool)
+        .await
+        .map_err(e500)?;
+    // Whoever knew the old password is logged out, like after a password reset. This session,
+    // registered as `reject_anonymous_users` checked, stays logged in.
+    let session_id = session
+        .get_session_id()
+        .map_err(e500)?
+        .ok_or_else(|| e500("The session id is missing."))?;
+    revoke_other_sessions(user_id, session_id, &pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("Your password has been changed.").send();
+    Ok(see_other("/admin/password"))
 }
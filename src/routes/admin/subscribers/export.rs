@@ -0,0 +1,91 @@
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType, CONTENT_TYPE},
+    web, HttpResponse,
+};
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+
+use crate::authentication::reject_viewers;
+
+/// Spreadsheets run cells starting with one of these as formulas.
+const FORMULA_PREFIXES: [char; 4] = ['=', '+', '-', '@'];
+
+/// Streams every subscriber as CSV, straight from Postgres: the list is never held in memory.
+/// The whole list of contact details is only available to editors and owners.
+///
+/// The file can be imported back as is.
+#[tracing::instrument(name = "Export subscribers as CSV", skip(pool))]
+#[actix_web::get(
+    "/subscribers/export",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn export_subscribers(pool: web::Data<PgPool>) -> HttpResponse {
+    let pool = pool.into_inner();
+    let rows = async_stream::try_stream! {
+        yield csv_line(&["email", "name", "status", "subscribed_at"])?;
+        let mut subscribers = sqlx::query!(
+            r#"
+            SELECT email, name, status, subscribed_at
+            FROM subscriptions
+            ORDER BY subscribed_at, email
+            "#
+        )
+        .fetch(pool.as_ref());
+        while let Some(s) = subscribers.try_next().await? {
+            yield csv_line(&[&s.email, &s.name, &s.status, &s.subscribed_at.to_rfc3339()])?;
+        }
+    };
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/csv; charset=utf-8"))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .streaming::<_, anyhow::Error>(rows)
+}
+
+fn csv_line(fields: &[&str]) -> Result<web::Bytes, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(fields.iter().map(|field| neutralise_formula(field)))?;
+    Ok(writer.into_inner()?.into())
+}
+
+/// Emails and names come from subscribers: a leading `'` keeps spreadsheets from running them.
+fn neutralise_formula(field: &str) -> String {
+    if field.starts_with(FORMULA_PREFIXES) {
+        format!("'{}", field)
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Undoes `neutralise_formula`, for files exported by the application and imported back.
+pub(super) fn restore_formula(field: String) -> String {
+    match field.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(FORMULA_PREFIXES) => rest.to_owned(),
+        _ => field,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_line, restore_formula};
+
+    #[test]
+    fn cells_that_look_like_formulas_are_neutralised() {
+        let line = csv_line(&["=cmd@example.com", "+Ursula", "-", "@sum", "Ursula"]).unwrap();
+        assert_eq!(&line[..], b"'=cmd@example.com,'+Ursula,'-,'@sum,Ursula\n");
+    }
+
+    #[test]
+    fn neutralised_cells_are_restored_on_import() {
+        assert_eq!(
+            restore_formula("'=cmd@example.com".into()),
+            "=cmd@example.com"
+        );
+        assert_eq!(
+            restore_formula("'ursula@example.com".into()),
+            "'ursula@example.com"
+        );
+    }
+}
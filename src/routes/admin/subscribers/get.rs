@@ -0,0 +1,245 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::{e400, e500, escape_html};
+
+const PAGE_SIZE: i64 = 50;
+const STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+
+#[derive(serde::Deserialize, Debug)]
+pub struct QueryParameters {
+    #[serde(default)]
+    search: String,
+    #[serde(default)]
+    status: String,
+    page: Option<i64>,
+}
+
+#[tracing::instrument(name = "List subscribers", skip(pool, flash_messages))]
+#[actix_web::get("/subscribers")]
+pub async fn list_subscribers(
+    parameters: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let QueryParameters {
+        search,
+        status,
+        page,
+    } = parameters.0;
+    let search = search.trim();
+    if !status.is_empty() && !STATUSES.contains(&status.as_str()) {
+        return Err(e400(format!(
+            "`{}` is not a valid subscription status.",
+            status
+        )));
+    }
+    let page = page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("Pages are numbered from 1."));
+    }
+    let filter = Filter {
+        search: (!search.is_empty()).then(|| like_pattern(search)),
+        status: (!status.is_empty()).then_some(status.as_str()),
+    };
+    let (subscribers, n_subscribers) = get_subscribers(&pool, &filter, page).await.map_err(e500)?;
+    let n_pages = ((n_subscribers + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    let mut status_options = String::from(r#"<option value="">All</option>"#);
+    for s in STATUSES {
+        let selected = if s == status { " selected" } else { "" };
+        write!(
+            status_options,
+            r#"<option value="{s}"{selected}>{s}</option>"#
+        )
+        .unwrap();
+    }
+    let mut rows_html = String::new();
+    for subscriber in subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{email}</td>
+                <td>{name}</td>
+                <td>{status}</td>
+                <td>{subscribed_at}</td>
+                <td>{actions}</td>
+            </tr>"#,
+            email = escape_html(&subscriber.email),
+            name = escape_html(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+            actions = actions_html(&subscriber),
+        )
+        .unwrap();
+    }
+    let search = escape_html(search);
+    let mut pagination_html = String::new();
+    if page > 1 {
+        pagination_html.push_str(&page_link(&search, &status, page - 1, "Previous"));
+    }
+    write!(pagination_html, "<span>Page {page} of {n_pages}</span>").unwrap();
+    if page < n_pages {
+        pagination_html.push_str(&page_link(&search, &status, page + 1, "Next"));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Subscribers</title>
+    </head>
+    <body>
+        {msg_html}
+        <form action="/admin/subscribers" method="get">
+            <input type="search" name="search" placeholder="Email or name" value="{search}">
+            <select name="status">{status_options}</select>
+            <button type="submit">Filter</button>
+        </form>
+        <p>
+            <a href="/admin/subscribers/import">Import from CSV</a> |
+            <a href="/admin/subscribers/export">Export as CSV</a>
+        </p>
+        <p>{n_subscribers} subscriber(s)</p>
+        <table>
+            <tr>
+                <th>Email</th>
+                <th>Name</th>
+                <th>Status</th>
+                <th>Subscribed at</th>
+                <th></th>
+            </tr>
+            {rows_html}
+        </table>
+        {pagination_html}
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+    </html>
+    "#,
+        )))
+}
+
+/// The buttons available for a subscriber, depending on the state of their subscription.
+fn actions_html(subscriber: &Subscriber) -> String {
+    let mut actions = vec![];
+    if subscriber.status == "pending_confirmation" {
+        actions.push(("resend_confirmation", "Resend confirmation"));
+    }
+    if subscriber.status != "confirmed" {
+        actions.push(("confirm", "Confirm"));
+    }
+    if subscriber.status != "unsubscribed" {
+        actions.push(("unsubscribe", "Unsubscribe"));
+    }
+    actions.push(("delete", "Delete"));
+    actions
+        .into_iter()
+        .map(|(action, label)| {
+            format!(
+                r#"<form action="/admin/subscribers/{}/{action}" method="post"><button type="submit">{label}</button></form>"#,
+                subscriber.id
+            )
+        })
+        .collect()
+}
+
+/// Pagination goes through a form so that the current filters are carried over as they are.
+fn page_link(search: &str, status: &str, page: i64, label: &str) -> String {
+    format!(
+        r#"<form action="/admin/subscribers" method="get">
+            <input type="hidden" name="search" value="{search}">
+            <input type="hidden" name="status" value="{status}">
+            <input type="hidden" name="page" value="{page}">
+            <button type="submit">{label}</button>
+        </form>"#
+    )
+}
+
+/// Matches the search text anywhere, treating `%` and `_` literally.
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+struct Filter<'a> {
+    search: Option<String>,
+    status: Option<&'a str>,
+}
+
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get a page of subscribers", skip(pool, filter))]
+async fn get_subscribers(
+    pool: &PgPool,
+    filter: &Filter<'_>,
+    page: i64,
+) -> Result<(Vec<Subscriber>, i64), anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, email
+        LIMIT $3
+        OFFSET $4
+        "#,
+        filter.search,
+        filter.status,
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve a page of subscribers.")?;
+    let n_subscribers = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2)
+        "#,
+        filter.search,
+        filter.status
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the subscribers.")?
+    .count;
+    Ok((subscribers, n_subscribers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::like_pattern;
+
+    #[test]
+    fn like_wildcards_in_the_search_text_are_escaped() {
+        assert_eq!(like_pattern("50%_off"), r"%50\%\_off%");
+    }
+}
@@ -0,0 +1,56 @@
+use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use std::fmt::Write;
+
+use crate::{authentication::reject_viewers, utils::escape_html};
+
+#[actix_web::get(
+    "/subscribers/import",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn import_subscribers_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    // Import errors quote the content of the uploaded file.
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Import subscribers</title>
+    </head>
+    <body>
+        {msg_html}
+        <p>
+            Upload a CSV file with a header row and (at least) an <code>email</code> and a
+            <code>name</code> column. Nothing is imported if any line is invalid, subscribers that
+            already exist are skipped.
+        </p>
+        <form action="/admin/subscribers/import" method="post" enctype="multipart/form-data">
+            <input type="file" name="file" accept=".csv,text/csv" required>
+            <br>
+            <label>
+                <input type="radio" name="mode" value="send_confirmation" checked>
+                Send a confirmation email to every imported subscriber
+            </label>
+            <br>
+            <label>
+                <input type="radio" name="mode" value="confirmed">
+                Mark imported subscribers as confirmed
+            </label>
+            <br>
+            <button type="submit">Import</button>
+        </form>
+        <p><a href="/admin/subscribers">&lt;- Back</a></p>
+    </body>
+    </html>
+    "#,
+        )))
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::import_subscribers_form;
+pub use post::import_subscribers;
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+
+use actix_multipart::form::{bytes::Bytes, text::Text, MultipartForm};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::reject_viewers,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    routes::{
+        subscriptions::{
+            enqueue_confirmation_email, generate_subscription_token, insert_subscriber, store_token,
+        },
+        subscriptions_confirm::confirm_subscriber,
+    },
+    startup::ApplicationBaseUrl,
+    utils::{e500, see_other},
+};
+
+use super::super::export::restore_formula;
+
+/// Past this many invalid lines, the admin gets a count rather than one message per line.
+const MAX_REPORTED_ERRORS: usize = 20;
+
+#[derive(serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Imported subscribers have to confirm their email, like everybody else.
+    SendConfirmation,
+    /// The list has already been confirmed elsewhere.
+    Confirmed,
+}
+
+#[derive(MultipartForm)]
+pub struct ImportForm {
+    #[multipart(limit = "2MiB")]
+    file: Bytes,
+    mode: Text<ImportMode>,
+}
+
+#[tracing::instrument(name = "Import subscribers from CSV", skip_all, fields(mode=?form.mode.0))]
+#[actix_web::post(
+    "/subscribers/import",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn import_subscribers(
+    form: MultipartForm<ImportForm>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ImportForm { file, mode } = form.into_inner();
+    let subscribers = match parse_csv(&file.data) {
+        Ok(subscribers) => subscribers,
+        Err(errors) => {
+            FlashMessage::error("Nothing was imported, please fix the following errors:").send();
+            for error in errors.iter().take(MAX_REPORTED_ERRORS) {
+                FlashMessage::error(error).send();
+            }
+            if errors.len() > MAX_REPORTED_ERRORS {
+                FlashMessage::error(format!(
+                    "... and {} more.",
+                    errors.len() - MAX_REPORTED_ERRORS
+                ))
+                .send();
+            }
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let (mut n_imported, mut n_skipped) = (0, 0);
+    for subscriber in &subscribers {
+        // Subscribers that are already on the list are left alone.
+        let subscriber_id = match insert_subscriber(&mut transaction, subscriber)
+            .await
+            .context("Failed to insert an imported subscriber in the database.")
+            .map_err(e500)?
+        {
+            Some(subscriber_id) => subscriber_id,
+            None => {
+                n_skipped += 1;
+                continue;
+            }
+        };
+        match mode.0 {
+            ImportMode::Confirmed => confirm_subscriber(&mut transaction, subscriber_id)
+                .await
+                .context("Failed to confirm an imported subscriber.")
+                .map_err(e500)?,
+            ImportMode::SendConfirmation => {
+                let subscription_token = generate_subscription_token();
+                store_token(&mut transaction, subscriber_id, &subscription_token)
+                    .await
+                    .context("Failed to store the confirmation token of an imported subscriber.")
+                    .map_err(e500)?;
+                enqueue_confirmation_email(
+                    &mut transaction,
+                    subscriber,
+                    &base_url.0,
+                    &subscription_token,
+                )
+                .await
+                .map_err(e500)?;
+            }
+        }
+        n_imported += 1;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "{} subscriber(s) imported, {} already subscribed and skipped.",
+        n_imported, n_skipped
+    ))
+    .send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+#[derive(serde::Deserialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+}
+
+/// Validates every line of the file, returning either all the subscribers or all the errors.
+fn parse_csv(data: &[u8]) -> Result<Vec<NewSubscriber>, Vec<String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return Err(vec![format!("The file could not be read: {}", e)]),
+    };
+    let mut subscribers = vec![];
+    let mut errors = vec![];
+    let mut seen_emails = HashSet::new();
+    for record in reader.records() {
+        let parsed = record.map_err(|e| e.to_string()).and_then(|record| {
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            let subscriber = record
+                .deserialize::<CsvRow>(Some(&headers))
+                .map_err(|e| e.to_string())
+                .and_then(|row| {
+                    Ok(NewSubscriber {
+                        email: SubscriberEmail::parse(restore_formula(row.email))?,
+                        name: SubscriberName::parse(restore_formula(row.name))?,
+                    })
+                })
+                .map_err(|e| format!("Line {}: {}", line, e))?;
+            if !seen_emails.insert(subscriber.email.as_ref().to_lowercase()) {
+                return Err(format!(
+                    "Line {}: {} appears more than once in the file.",
+                    line,
+                    subscriber.email.as_ref()
+                ));
+            }
+            Ok(subscriber)
+        });
+        match parsed {
+            Ok(subscriber) => subscribers.push(subscriber),
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    if subscribers.is_empty() {
+        return Err(vec!["The file does not contain any subscriber.".into()]);
+    }
+    Ok(subscribers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn columns_are_matched_by_name_and_extra_columns_are_ignored() {
+        let csv = "status,name,email\nconfirmed,Ursula, ursula@example.com \n";
+        let subscribers = assert_ok!(parse_csv(csv.as_bytes()));
+        assert_eq!(subscribers.len(), 1);
+        assert_eq!(subscribers[0].email.as_ref(), "ursula@example.com");
+        assert_eq!(subscribers[0].name.as_ref(), "Ursula");
+    }
+
+    #[test]
+    fn every_invalid_line_is_reported() {
+        let csv = "email,name\n\
+                   ursula@example.com,Ursula\n\
+                   not-an-email,Octavia\n\
+                   ursa@example.com,\n\
+                   ursula@example.com,Ursula again\n";
+        let errors = assert_err!(parse_csv(csv.as_bytes()));
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("Line 3: "));
+        assert!(errors[1].starts_with("Line 4: "));
+        assert_eq!(
+            errors[2],
+            "Line 5: ursula@example.com appears more than once in the file."
+        );
+    }
+
+    #[test]
+    fn a_missing_column_is_reported() {
+        let csv = "email\nursula@example.com\n";
+        let errors = assert_err!(parse_csv(csv.as_bytes()));
+        assert!(errors[0].starts_with("Line 2: "));
+    }
+
+    #[test]
+    fn an_empty_file_is_rejected() {
+        assert_err!(parse_csv(b"email,name\n"));
+    }
+}
@@ -0,0 +1,9 @@
+mod export;
+mod get;
+mod import;
+mod post;
+
+pub use export::export_subscribers;
+pub use get::list_subscribers;
+pub use import::*;
+pub use post::*;
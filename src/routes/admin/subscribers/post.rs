@@ -0,0 +1,213 @@
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::reject_viewers,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    routes::{
+        subscriptions::{
+            enqueue_confirmation_email, generate_subscription_token, restart_confirmation,
+            store_token,
+        },
+        subscriptions_confirm::{confirm_subscriber, delete_tokens},
+        subscriptions_privacy::{erase_subscriber, ErasureRequester},
+        subscriptions_unsubscribe::cancel_pending_deliveries,
+    },
+    startup::ApplicationBaseUrl,
+    utils::{e500, see_other},
+};
+
+struct Subscriber {
+    email: String,
+    name: String,
+    status: String,
+}
+
+#[tracing::instrument(name = "Resend a confirmation email", skip(pool, base_url))]
+#[post(
+    "/subscribers/{subscriber_id}/resend_confirmation",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn resend_confirmation(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = begin(&pool).await.map_err(e500)?;
+    match get_subscriber(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) if subscriber.status == "pending_confirmation" => {
+            let new_subscriber = NewSubscriber {
+                email: SubscriberEmail::parse(subscriber.email).map_err(e500)?,
+                name: SubscriberName::parse(subscriber.name).map_err(e500)?,
+            };
+            // Links sent so far stop working, only the new one can be used.
+            restart_confirmation(&mut transaction, subscriber_id, &new_subscriber.name)
+                .await
+                .context("Failed to invalidate the previous confirmation links.")
+                .map_err(e500)?;
+            let subscription_token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .context("Failed to store a new confirmation token.")
+                .map_err(e500)?;
+            enqueue_confirmation_email(
+                &mut transaction,
+                &new_subscriber,
+                &base_url.0,
+                &subscription_token,
+            )
+            .await
+            .map_err(e500)?;
+            commit(transaction).await.map_err(e500)?;
+            FlashMessage::info(format!(
+                "A new confirmation email has been sent to {}.",
+                new_subscriber.email.as_ref()
+            ))
+            .send();
+        }
+        Some(_) => {
+            FlashMessage::error("Only pending subscribers can be sent a confirmation email.").send()
+        }
+        None => unknown_subscriber().send(),
+    }
+    Ok(see_other("/admin/subscribers"))
+}
+
+#[tracing::instrument(name = "Confirm a subscriber manually", skip(pool))]
+#[post(
+    "/subscribers/{subscriber_id}/confirm",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn confirm_subscriber_manually(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = begin(&pool).await.map_err(e500)?;
+    match get_subscriber(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) => {
+            confirm_subscriber(&mut transaction, subscriber_id)
+                .await
+                .context("Failed to confirm the subscriber.")
+                .map_err(e500)?;
+            delete_tokens(&mut transaction, subscriber_id)
+                .await
+                .context("Failed to delete the confirmation tokens of the subscriber.")
+                .map_err(e500)?;
+            commit(transaction).await.map_err(e500)?;
+            FlashMessage::info(format!("{} has been confirmed.", subscriber.email)).send();
+        }
+        None => unknown_subscriber().send(),
+    }
+    Ok(see_other("/admin/subscribers"))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber manually", skip(pool))]
+#[post(
+    "/subscribers/{subscriber_id}/unsubscribe",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = begin(&pool).await.map_err(e500)?;
+    match get_subscriber(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) => {
+            sqlx::query!(
+                r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+                subscriber_id
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to update the subscriber status to `unsubscribed`.")
+            .map_err(e500)?;
+            delete_tokens(&mut transaction, subscriber_id)
+                .await
+                .context("Failed to delete the confirmation tokens of the subscriber.")
+                .map_err(e500)?;
+            cancel_pending_deliveries(&mut transaction, &subscriber.email)
+                .await
+                .context("Failed to cancel the pending deliveries of the subscriber.")
+                .map_err(e500)?;
+            commit(transaction).await.map_err(e500)?;
+            FlashMessage::info(format!("{} has been unsubscribed.", subscriber.email)).send();
+        }
+        None => unknown_subscriber().send(),
+    }
+    Ok(see_other("/admin/subscribers"))
+}
+
+#[tracing::instrument(name = "Delete a subscriber", skip(pool))]
+#[post(
+    "/subscribers/{subscriber_id}/delete",
+    wrap = "actix_web_lab::middleware::from_fn(reject_viewers)"
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = begin(&pool).await.map_err(e500)?;
+    match erase_subscriber(
+        &mut transaction,
+        subscriber_id.into_inner(),
+        ErasureRequester::Admin,
+    )
+    .await
+    .map_err(e500)?
+    {
+        Some(email) => {
+            commit(transaction).await.map_err(e500)?;
+            FlashMessage::info(format!("{} has been deleted.", email)).send();
+        }
+        None => unknown_subscriber().send(),
+    }
+    Ok(see_other("/admin/subscribers"))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn get_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"SELECT email, name, status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+    Ok(subscriber)
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, anyhow::Error> {
+    pool.begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+}
+
+async fn commit(transaction: Transaction<'_, Postgres>) -> Result<(), anyhow::Error> {
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")
+}
+
+fn unknown_subscriber() -> FlashMessage {
+    FlashMessage::error("There is no subscriber matching your request.")
+}
@@ -0,0 +1,90 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use qrcode::{render::svg, QrCode};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{get_two_factor_status, EncryptionKey, UserId},
+    routes::admin::dashboard::get_username,
+    utils::{e500, escape_html},
+};
+
+#[tracing::instrument(name = "Show the two-factor authentication settings", skip_all)]
+#[actix_web::get("/two_factor")]
+pub async fn two_factor_settings(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    encryption_key: web::Data<EncryptionKey>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let status = get_two_factor_status(user_id, &encryption_key, &pool)
+        .await
+        .map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+
+    let content = if status.enabled {
+        format!(
+            r#"<p>Two-factor authentication is enabled. You have {} recovery code(s) left.</p>
+        <form action="/admin/two_factor/disable" method="post">
+            <label>Current password
+                <input type="password" placeholder="Enter current password" name="current_password">
+            </label>
+            <button type="submit">Disable two-factor authentication</button>
+        </form>"#,
+            status.recovery_codes_left
+        )
+    } else if let Some(secret) = status.pending_secret {
+        let username = get_username(user_id, &pool).await.map_err(e500)?;
+        let uri = secret.provisioning_uri(&username);
+        let qr_code = QrCode::new(uri.as_bytes())
+            .map_err(e500)?
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build();
+        format!(
+            r#"<p>Scan this QR code with your authenticator app:</p>
+        {qr_code}
+        <p>Or enter this secret by hand: <code>{secret}</code></p>
+        <p>Then enter the code it shows to finish setting up two-factor authentication.</p>
+        <form action="/admin/two_factor/confirm" method="post">
+            <label>Code
+                <input type="text" inputmode="numeric" autocomplete="one-time-code" placeholder="123456" name="code">
+            </label>
+            <button type="submit">Enable</button>
+        </form>
+        <p><small>{uri}</small></p>"#,
+            secret = secret.to_base32(),
+            uri = escape_html(&uri),
+        )
+    } else {
+        r#"<p>Two-factor authentication is disabled. Once enabled, logging in also requires a code from an authenticator app.</p>
+        <form action="/admin/two_factor/enrol" method="post">
+            <button type="submit">Set up two-factor authentication</button>
+        </form>"#
+            .to_string()
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Two-factor authentication</title>
+    </head>
+    <body>
+        {msg_html}
+        {content}
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+    </html>
+    "#,
+        )))
+}
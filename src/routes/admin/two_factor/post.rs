@@ -0,0 +1,134 @@
+use actix_web::{http::header::ContentType, post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{
+        confirm_enrolment, disable_two_factor, is_two_factor_enabled, start_enrolment,
+        validate_credentials, AuthError, Credentials, EncryptionKey, PasswordHashing, UserId,
+    },
+    login_throttle::LoginThrottle,
+    routes::admin::dashboard::get_username,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmFormData {
+    code: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DisableFormData {
+    current_password: Secret<String>,
+}
+
+#[tracing::instrument(name = "Start setting up two-factor authentication", skip_all)]
+#[post("/two_factor/enrol")]
+pub async fn enrol_two_factor(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    encryption_key: web::Data<EncryptionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    if is_two_factor_enabled(user_id, &pool).await.map_err(e500)? {
+        FlashMessage::error("Two-factor authentication is already enabled.").send();
+        return Ok(see_other("/admin/two_factor"));
+    }
+    start_enrolment(user_id, &encryption_key, &pool)
+        .await
+        .map_err(e500)?;
+    Ok(see_other("/admin/two_factor"))
+}
+
+/// Shows the recovery codes right away rather than redirecting: they are not stored in clear,
+/// this response is the only place they can be read from.
+#[tracing::instrument(name = "Finish setting up two-factor authentication", skip_all)]
+#[post("/two_factor/confirm")]
+pub async fn confirm_two_factor(
+    form: web::Form<ConfirmFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    encryption_key: web::Data<EncryptionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let recovery_codes =
+        match confirm_enrolment(*user_id.into_inner(), &form.code, &encryption_key, &pool)
+            .await
+            .map_err(e500)?
+        {
+            Some(recovery_codes) => recovery_codes,
+            None => {
+                FlashMessage::error("The code is not valid, please try again.").send();
+                return Ok(see_other("/admin/two_factor"));
+            }
+        };
+    let codes_html: String = recovery_codes
+        .iter()
+        .map(|code| format!("<li><code>{}</code></li>", code))
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Two-factor authentication</title>
+    </head>
+    <body>
+        <p>Two-factor authentication is enabled.</p>
+        <p>
+            Keep these recovery codes somewhere safe, each of them can be used once to log in
+            without your authenticator app. They will not be shown again.
+        </p>
+        <ul>{codes_html}</ul>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+    </html>
+    "#,
+        )))
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip_all)]
+#[post("/two_factor/disable")]
+pub async fn disable_two_factor_authentication(
+    form: web::Form<DisableFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let credentials = Credentials {
+        username: get_username(user_id, &pool).await.map_err(e500)?,
+        password: form.0.current_password,
+    };
+    // Throttled like a login, see `change_password`.
+    let client_address = login_throttle.client_address(&request);
+    let attempt = match login_throttle
+        .reserve_attempt(&credentials.username, client_address.as_deref())
+        .await
+        .map_err(e500)?
+    {
+        Ok(attempt) => attempt,
+        Err(throttled) => {
+            FlashMessage::error(throttled.to_string()).send();
+            return Ok(see_other("/admin/two_factor"));
+        }
+    };
+    if let Err(e) = validate_credentials(credentials, &hashing, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/two_factor"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+    login_throttle.record_success(attempt).await.map_err(e500)?;
+    disable_two_factor(user_id, &pool).await.map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/two_factor"))
+}
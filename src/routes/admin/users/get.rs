@@ -0,0 +1,188 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{reject_non_owners, Role, UserId},
+    utils::{e500, escape_html},
+};
+
+#[tracing::instrument(name = "List admin users", skip(pool, flash_messages))]
+#[actix_web::get(
+    "/users",
+    wrap = "actix_web_lab::middleware::from_fn(reject_non_owners)"
+)]
+pub async fn list_users(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_user_id = *user_id.into_inner();
+    let users = get_users(&pool).await.map_err(e500)?;
+    let invites = get_pending_invites(&pool).await.map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    let mut users_html = String::new();
+    for user in users {
+        let actions = if user.user_id == current_user_id {
+            "(you)".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/users/{id}/role" method="post">
+                    <select name="role">{options}</select>
+                    <button type="submit">Change role</button>
+                </form>
+                <form action="/admin/users/{id}/delete" method="post">
+                    <button type="submit">Remove</button>
+                </form>"#,
+                id = user.user_id,
+                options = role_options(Some(&user.role)),
+            )
+        };
+        writeln!(
+            users_html,
+            r#"<tr>
+                <td>{username}</td>
+                <td>{email}</td>
+                <td>{role}</td>
+                <td>{actions}</td>
+            </tr>"#,
+            username = escape_html(&user.username),
+            email = escape_html(user.email.as_deref().unwrap_or("")),
+            role = user.role,
+        )
+        .unwrap();
+    }
+    let mut invites_html = String::new();
+    for invite in invites {
+        let email = escape_html(&invite.email);
+        writeln!(
+            invites_html,
+            r#"<tr>
+                <td>{email}</td>
+                <td>{role}</td>
+                <td>{expires_at}</td>
+                <td>
+                    <form action="/admin/users/invites/revoke" method="post">
+                        <input type="hidden" name="email" value="{email}">
+                        <button type="submit">Revoke</button>
+                    </form>
+                </td>
+            </tr>"#,
+            role = invite.role,
+            expires_at = invite.expires_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    let invite_role_options = role_options(Some(Role::Editor.as_str()));
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Users</title>
+    </head>
+    <body>
+        {msg_html}
+        <p>
+            Viewers can look around, editors can also publish issues and manage subscribers,
+            owners can also manage users.
+        </p>
+        <table>
+            <tr>
+                <th>Username</th>
+                <th>Email</th>
+                <th>Role</th>
+                <th></th>
+            </tr>
+            {users_html}
+        </table>
+        <h2>Pending invites</h2>
+        <table>
+            <tr>
+                <th>Email</th>
+                <th>Role</th>
+                <th>Expires at</th>
+                <th></th>
+            </tr>
+            {invites_html}
+        </table>
+        <h2>Invite a user</h2>
+        <form action="/admin/users/invite" method="post">
+            <input type="email" name="email" placeholder="Email">
+            <select name="role">{invite_role_options}</select>
+            <button type="submit">Send invite</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+    </html>
+    "#,
+        )))
+}
+
+fn role_options(selected: Option<&str>) -> String {
+    Role::ALL
+        .iter()
+        .map(|role| {
+            let role = role.as_str();
+            let selected = if Some(role) == selected {
+                " selected"
+            } else {
+                ""
+            };
+            format!(r#"<option value="{role}"{selected}>{role}</option>"#)
+        })
+        .collect()
+}
+
+struct User {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: String,
+}
+
+struct Invite {
+    email: String,
+    role: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_users(pool: &PgPool) -> Result<Vec<User>, anyhow::Error> {
+    sqlx::query_as!(
+        User,
+        r#"SELECT user_id, username, email, role FROM users ORDER BY username"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the users.")
+}
+
+/// Expired invites are left out, they are replaced when the same email is invited again.
+#[tracing::instrument(skip(pool))]
+async fn get_pending_invites(pool: &PgPool) -> Result<Vec<Invite>, anyhow::Error> {
+    sqlx::query_as!(
+        Invite,
+        r#"
+        SELECT email, role, expires_at
+        FROM user_invites
+        WHERE expires_at > now()
+        ORDER BY created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the pending invites.")
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::list_users;
+pub use post::*;
@@ -0,0 +1,270 @@
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{reject_non_owners, revoke_all_sessions, Role, UserId},
+    domain::SubscriberEmail,
+    email_outbox::enqueue_email,
+    email_templates::{EmailTemplate, UserInviteEmail},
+    routes::{admin::dashboard::get_username, subscriptions::generate_subscription_token},
+    startup::ApplicationBaseUrl,
+    utils::{e500, see_other},
+};
+
+/// How long an invite link stays valid after it has been sent.
+pub const INVITE_TTL_HOURS: i64 = 72;
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    email: String,
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RevokeFormData {
+    email: String,
+}
+
+/// Emails a single-use link to create an account with the chosen role. Inviting an email again
+/// replaces its pending invite.
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, user_id, pool, base_url),
+    fields(role = form.role.as_str())
+)]
+#[post(
+    "/users/invite",
+    wrap = "actix_web_lab::middleware::from_fn(reject_non_owners)"
+)]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let InviteFormData { email, role } = form.0;
+    let email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let inviter_id = *user_id.into_inner();
+    let inviter = get_username(inviter_id, &pool).await.map_err(e500)?;
+
+    let mut transaction = begin(&pool).await.map_err(e500)?;
+    let existing_user = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up an existing user.")
+    .map_err(e500)?;
+    if existing_user.is_some() {
+        FlashMessage::error(format!("{} already has an account.", email.as_ref())).send();
+        return Ok(see_other("/admin/users"));
+    }
+    let invite_token = generate_subscription_token();
+    store_invite(&mut transaction, &email, role, inviter_id, &invite_token)
+        .await
+        .context("Failed to store the invite.")
+        .map_err(e500)?;
+    let link = format!(
+        "{}/invites/accept?invite_token={}",
+        base_url.0, invite_token
+    );
+    let rendered = UserInviteEmail {
+        inviter: &inviter,
+        role: role.as_str(),
+        link: &link,
+        expires_in_hours: INVITE_TTL_HOURS,
+    }
+    .render()
+    .context("Failed to render the invite email.")
+    .map_err(e500)?;
+    enqueue_email(
+        &mut transaction,
+        &email,
+        "You have been invited to help run our newsletter",
+        &rendered.html,
+        &rendered.text,
+    )
+    .await
+    .context("Failed to store the invite email in the outbox.")
+    .map_err(e500)?;
+    commit(transaction).await.map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "An invite has been sent to {}, as {}.",
+        email.as_ref(),
+        role
+    ))
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Revoke an invite", skip(form, pool))]
+#[post(
+    "/users/invites/revoke",
+    wrap = "actix_web_lab::middleware::from_fn(reject_non_owners)"
+)]
+pub async fn revoke_invite(
+    form: web::Form<RevokeFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let result = sqlx::query!(r#"DELETE FROM user_invites WHERE email = $1"#, form.email)
+        .execute(pool.get_ref())
+        .await
+        .context("Failed to delete an invite.")
+        .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        FlashMessage::error("There is no pending invite for this email.").send();
+    } else {
+        FlashMessage::info(format!(
+            "The invite sent to {} has been revoked.",
+            form.email
+        ))
+        .send();
+    }
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Change the role of a user", skip(form, current_user_id, pool))]
+#[post(
+    "/users/{user_id}/role",
+    wrap = "actix_web_lab::middleware::from_fn(reject_non_owners)"
+)]
+pub async fn change_user_role(
+    user_id: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    current_user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // Owners cannot demote themselves, so there is always at least one owner left.
+    if user_id == **current_user_id {
+        FlashMessage::error("You cannot change your own role.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let username = sqlx::query!(
+        r#"UPDATE users SET role = $1 WHERE user_id = $2 RETURNING username"#,
+        form.role.as_str(),
+        user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to update the role of a user.")
+    .map_err(e500)?
+    .map(|row| row.username);
+    match username {
+        Some(username) => FlashMessage::info(format!("{} is now {}.", username, form.role)).send(),
+        None => unknown_user().send(),
+    }
+    Ok(see_other("/admin/users"))
+}
+
+/// Deletes a user along with their sessions, which logs them out everywhere.
+#[tracing::instrument(name = "Remove a user", skip(current_user_id, pool))]
+#[post(
+    "/users/{user_id}/delete",
+    wrap = "actix_web_lab::middleware::from_fn(reject_non_owners)"
+)]
+pub async fn remove_user(
+    user_id: web::Path<Uuid>,
+    current_user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if user_id == **current_user_id {
+        FlashMessage::error("You cannot remove yourself.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let mut transaction = begin(&pool).await.map_err(e500)?;
+    revoke_all_sessions(&mut transaction, user_id)
+        .await
+        .map_err(e500)?;
+    sqlx::query!(r#"DELETE FROM idempotency WHERE user_id = $1"#, user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the saved responses of a user.")
+        .map_err(e500)?;
+    let username = sqlx::query!(
+        r#"DELETE FROM users WHERE user_id = $1 RETURNING username"#,
+        user_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to delete a user.")
+    .map_err(e500)?
+    .map(|row| row.username);
+    match username {
+        Some(username) => {
+            commit(transaction).await.map_err(e500)?;
+            FlashMessage::info(format!("{} has been removed.", username)).send();
+        }
+        None => unknown_user().send(),
+    }
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(skip(transaction, invite_token))]
+async fn store_invite(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    role: Role,
+    invited_by: Uuid,
+    invite_token: &str,
+) -> Result<(), sqlx::Error> {
+    let created_at = Utc::now();
+    let expires_at = created_at + chrono::Duration::hours(INVITE_TTL_HOURS);
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invites
+            (invite_token, email, role, invited_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (email) DO UPDATE SET
+            invite_token = EXCLUDED.invite_token,
+            role = EXCLUDED.role,
+            invited_by = EXCLUDED.invited_by,
+            created_at = EXCLUDED.created_at,
+            expires_at = EXCLUDED.expires_at
+        "#,
+        invite_token,
+        email.as_ref(),
+        role.as_str(),
+        invited_by,
+        created_at,
+        expires_at
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, anyhow::Error> {
+    pool.begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+}
+
+async fn commit(transaction: Transaction<'_, Postgres>) -> Result<(), anyhow::Error> {
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update users.")
+}
+
+fn unknown_user() -> FlashMessage {
+    FlashMessage::error("There is no user matching your request.")
+}
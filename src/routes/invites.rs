@@ -0,0 +1,259 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{hash_password, BreachedPasswords, NewPassword, PasswordHashing, Role},
+    utils::{escape_html, see_other},
+};
+
+use super::error_chain_fmt;
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct InviteParameters {
+    invite_token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptInviteFormData {
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum InviteError {
+    #[error("There is no invite associated with the provided token.")]
+    UnknownToken,
+    #[error("The invite has expired, please ask for a new one.")]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for InviteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for InviteError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Show the invite acceptance form", skip_all)]
+#[get("/invites/accept")]
+pub async fn accept_invite_form(
+    parameters: web::Query<InviteParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, InviteError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let invite = get_valid_invite(&mut transaction, &parameters.invite_token).await?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Create your account</title>
+    </head>
+    <body>
+        {msg_html}
+        <p>You have been invited as {role}, choose your username and password to get started.</p>
+        <form
+            action="/invites/accept?invite_token={token}"
+            method="post"
+        >
+            <label>Username
+                <input type="text" placeholder="Enter Username" name="username">
+            </label>
+            <br>
+            <label>Password
+                <input type="password" placeholder="Enter Password" name="password">
+            </label>
+            <br>
+            <label>Confirm password
+                <input type="password" placeholder="Type the password again" name="password_check">
+            </label>
+            <br>
+            <button type="submit">Create my account</button>
+        </form>
+    </body>
+    </html>
+              "#,
+            role = invite.role,
+            token = escape_html(&parameters.invite_token),
+        )))
+}
+
+/// Creates the account and consumes the invite. Mistakes in the form send the user back to it,
+/// the invite stays valid until an account has been created.
+#[tracing::instrument(name = "Accept an invite", skip_all, fields(username = %form.username))]
+#[post("/invites/accept")]
+pub async fn accept_invite(
+    parameters: web::Query<InviteParameters>,
+    form: web::Form<AcceptInviteFormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    breached_passwords: web::Data<BreachedPasswords>,
+) -> Result<HttpResponse, InviteError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let invite = get_valid_invite(&mut transaction, &parameters.invite_token).await?;
+    // The token matches a stored one, it is safe to put back in a URL as is.
+    let form_url = format!("/invites/accept?invite_token={}", parameters.invite_token);
+
+    let AcceptInviteFormData {
+        username,
+        password,
+        password_check,
+    } = form.0;
+    let username = username.trim();
+    if let Err(e) = validate_username(username) {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&form_url));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(see_other(&form_url));
+    }
+    let password = match NewPassword::parse(password, &breached_passwords) {
+        Ok(password) => password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&form_url));
+        }
+    };
+    let username_taken = sqlx::query!(r#"SELECT user_id FROM users WHERE username = $1"#, username)
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to look up an existing username.")?
+        .is_some();
+    if username_taken {
+        FlashMessage::error("This username is already taken.").send();
+        return Ok(see_other(&form_url));
+    }
+
+    let password_hash = hash_password(password.into(), &hashing).await?;
+    let insert_result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        invite.role.as_str(),
+        invite.email
+    )
+    .execute(&mut transaction)
+    .await;
+    // The username, or the email, can be claimed by another account between the check above
+    // and the insert.
+    if let Err(sqlx::Error::Database(e)) = &insert_result {
+        let message = match e.constraint() {
+            Some("users_username_key") => Some("This username is already taken."),
+            Some("users_email_key") => Some("An account already exists for this email address."),
+            _ => None,
+        };
+        if let Some(message) = message {
+            FlashMessage::error(message).send();
+            return Ok(see_other(&form_url));
+        }
+    }
+    insert_result.context("Failed to create the invited user.")?;
+    sqlx::query!(
+        r#"DELETE FROM user_invites WHERE invite_token = $1"#,
+        parameters.invite_token
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete an accepted invite.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an invite.")?;
+
+    FlashMessage::info("Your account has been created, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+/// Usernames are shown across the admin pages: they are limited to characters that need no
+/// escaping anywhere.
+fn validate_username(username: &str) -> Result<(), String> {
+    if username.is_empty() {
+        return Err("The username cannot be empty.".into());
+    }
+    if username.len() > MAX_USERNAME_LENGTH {
+        return Err(format!(
+            "The username must be at most {} characters long.",
+            MAX_USERNAME_LENGTH
+        ));
+    }
+    let is_allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+    if !username.chars().all(is_allowed) {
+        return Err(
+            "The username can only contain letters, digits, dots, underscores and dashes.".into(),
+        );
+    }
+    Ok(())
+}
+
+struct Invite {
+    email: String,
+    role: Role,
+}
+
+/// Looks an invite up, locking it until the transaction ends so that it cannot be used twice.
+async fn get_valid_invite(
+    transaction: &mut Transaction<'_, Postgres>,
+    invite_token: &str,
+) -> Result<Invite, InviteError> {
+    let invite = sqlx::query!(
+        r#"
+        SELECT email, role, expires_at
+        FROM user_invites
+        WHERE invite_token = $1
+        FOR UPDATE
+        "#,
+        invite_token
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the invite associated with the provided token.")?
+    .ok_or(InviteError::UnknownToken)?;
+    if invite.expires_at < Utc::now() {
+        return Err(InviteError::ExpiredToken);
+    }
+    Ok(Invite {
+        email: invite.email,
+        role: Role::parse(&invite.role)?,
+    })
+}
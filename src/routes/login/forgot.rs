@@ -0,0 +1,178 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_outbox::enqueue_email,
+    email_templates::{EmailTemplate, PasswordResetEmail},
+    login_throttle::LoginThrottle,
+    routes::subscriptions::generate_subscription_token,
+    startup::ApplicationBaseUrl,
+    utils::{e500, escape_html, see_other},
+};
+
+use super::reset::{hash_reset_token, RESET_TOKEN_TTL_MINUTES};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+}
+
+#[get("/login/forgot")]
+pub async fn forgot_password_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forgot your password?</title>
+</head>
+<body>
+    {msg_html}
+    <p>Enter your username, we will email you a link to choose a new password.</p>
+    <form action="/login/forgot" method="post">
+        <label>Username
+            <input
+                type="text"
+                placeholder="Enter Username"
+                name="username"
+            >
+        </label>
+        <button type="submit">Send me a link</button>
+    </form>
+    <p><a href="/login">&lt;- Back to login</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+/// Emails a reset link to the user, if they exist and we know their email address.
+///
+/// The response is the same either way, so that it cannot be used to find out usernames.
+/// Requests are throttled per username and per client address, so that it cannot be used to
+/// flood a user's inbox either.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, pool, base_url, request, login_throttle),
+    fields(username = %form.username)
+)]
+#[post("/login/forgot")]
+pub async fn request_password_reset(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let client_address = login_throttle.client_address(&request);
+    if let Err(throttled) = login_throttle
+        .reserve_reset_request(&form.username, client_address.as_deref())
+        .await
+        .map_err(e500)?
+    {
+        let minutes = (throttled.retry_after_seconds() + 59) / 60;
+        FlashMessage::error(format!(
+            "Too many password reset requests. Please try again in {} minute(s).",
+            minutes
+        ))
+        .send();
+        return Ok(see_other("/login/forgot"));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let user = sqlx::query!(
+        r#"SELECT user_id, username, email FROM users WHERE username = $1"#,
+        form.username
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up the user.")
+    .map_err(e500)?;
+    match user {
+        Some(user) => match user.email {
+            Some(email) => {
+                let email = SubscriberEmail::parse(email).map_err(e500)?;
+                let reset_token = generate_subscription_token();
+                store_reset_token(&mut transaction, user.user_id, &reset_token)
+                    .await
+                    .context("Failed to store the password reset token.")
+                    .map_err(e500)?;
+                let link = format!("{}/login/reset?reset_token={}", base_url.0, reset_token);
+                let rendered = PasswordResetEmail {
+                    username: &user.username,
+                    link: &link,
+                    expires_in_minutes: RESET_TOKEN_TTL_MINUTES,
+                }
+                .render()
+                .context("Failed to render the password reset email.")
+                .map_err(e500)?;
+                enqueue_email(
+                    &mut transaction,
+                    &email,
+                    "Reset your password",
+                    &rendered.html,
+                    &rendered.text,
+                )
+                .await
+                .context("Failed to store the password reset email in the outbox.")
+                .map_err(e500)?;
+            }
+            None => tracing::warn!("The user has no email address, no reset link can be sent."),
+        },
+        None => tracing::info!("Password reset requested for an unknown username."),
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to request a password reset.")
+        .map_err(e500)?;
+    FlashMessage::info(
+        "If this username has an email address, a link to reset its password has been sent to it.",
+    )
+    .send();
+    Ok(see_other("/login"))
+}
+
+/// Only the latest link sent to a user works.
+#[tracing::instrument(skip(transaction, reset_token))]
+async fn store_reset_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    reset_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let created_at = Utc::now();
+    let expires_at = created_at + chrono::Duration::minutes(RESET_TOKEN_TTL_MINUTES);
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_reset_token(reset_token),
+        user_id,
+        created_at,
+        expires_at
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -2,11 +2,13 @@ use actix_web::{get, http::header::ContentType, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
 use std::fmt::Write;
 
+use crate::utils::escape_html;
+
 #[get("/login")]
 pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
     let mut error_html = String::new();
     for m in flash_messages.iter() {
-        writeln!(error_html, "<p><i>{}</i><p>", m.content()).unwrap();
+        writeln!(error_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
     }
 
     HttpResponse::Ok()
@@ -37,6 +39,7 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
         </label>
         <button type="submit">Login</button>
     </form>
+    <p><a href="/login/forgot">Forgot your password?</a></p>
 </body>
 </html>"#,
         ))
@@ -1,13 +1,19 @@
-use actix_web::{error::InternalError, post, web, HttpResponse};
+use actix_web::{error::InternalError, post, web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use chrono::Utc;
 use reqwest::header::LOCATION;
 use secrecy::Secret;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials},
+    authentication::{
+        is_two_factor_enabled, register_session, validate_credentials, AuthError, Credentials,
+        PasswordHashing,
+    },
+    login_throttle::{LoginThrottle, Throttled},
     routes::error_chain_fmt,
-    session_state::TypedSession,
+    session_state::{PendingSecondFactor, TypedSession},
     utils::see_other,
 };
 
@@ -18,7 +24,7 @@ pub struct FormData {
 }
 
 #[tracing::instrument(
-    skip(form, pool, session),
+    skip(form, pool, session, request, login_throttle, hashing),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
     )]
 #[post("/login")]
@@ -26,22 +32,60 @@ pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    request: HttpRequest,
+    login_throttle: web::Data<LoginThrottle>,
+    hashing: web::Data<PasswordHashing>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
+    let username = form.0.username;
+    let client_address = login_throttle.client_address(&request);
+    tracing::Span::current().record("username", tracing::field::display(&username));
+    // Throttled attempts are turned down before spending any time on the password hash.
+    let attempt = match login_throttle
+        .reserve_attempt(&username, client_address.as_deref())
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        Ok(attempt) => attempt,
+        Err(throttled) => return Err(login_redirect(LoginError::Throttled(throttled))),
+    };
+
     let credentials = Credentials {
-        username: form.0.username,
+        username: username.clone(),
         password: form.0.password,
     };
-
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    match validate_credentials(credentials, &pool).await {
+    match validate_credentials(credentials, &hashing, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
             session.renew();
-            session
-                .insert_user_id(user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            if is_two_factor_enabled(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+            {
+                // The failures are only forgotten once the second factor is verified too.
+                login_throttle
+                    .release(attempt)
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                let pending = PendingSecondFactor {
+                    user_id,
+                    username,
+                    started_at: Utc::now().timestamp(),
+                };
+                session
+                    .insert_pending_second_factor(&pending)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(see_other("/login/two_factor"));
+            }
+            login_throttle
+                .record_success(attempt)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            start_session(&session, user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             Ok(see_other("/admin/dashboard"))
         }
+        // A failed attempt has already been recorded as such.
         Err(e) => {
             let e = match e {
                 AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
@@ -52,6 +96,19 @@ pub async fn login(
     }
 }
 
+/// Logs the user in, once every factor has been verified.
+pub(crate) async fn start_session(
+    session: &TypedSession,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    session.remove_pending_second_factor();
+    let session_id = register_session(user_id, pool).await?;
+    session.insert_user_id(user_id)?;
+    session.insert_session_id(session_id)?;
+    Ok(())
+}
+
 fn login_redirect(e: LoginError) -> InternalError<LoginError> {
     FlashMessage::error(e.to_string()).send();
     let response = HttpResponse::SeeOther()
@@ -64,6 +121,8 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    Throttled(Throttled),
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -0,0 +1,218 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{
+        hash_password, revoke_all_sessions, BreachedPasswords, NewPassword, PasswordHashing,
+    },
+    routes::error_chain_fmt,
+    utils::{escape_html, see_other},
+};
+
+/// How long a password reset link stays valid after it has been sent.
+pub const RESET_TOKEN_TTL_MINUTES: i64 = 30;
+
+#[derive(serde::Deserialize)]
+pub struct ResetParameters {
+    reset_token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PasswordResetError {
+    #[error("The link is not valid, please make a new request.")]
+    UnknownToken,
+    #[error("The link has expired, please make a new request.")]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PasswordResetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Show the password reset form", skip_all)]
+#[get("/login/reset")]
+pub async fn reset_password_form(
+    parameters: web::Query<ResetParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, PasswordResetError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    get_valid_reset_token(&mut transaction, &parameters.reset_token).await?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset your password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/login/reset?reset_token={token}" method="post">
+        <label>New password
+            <input
+                type="password"
+                placeholder="Enter new password"
+                name="new_password"
+            >
+        </label>
+        <br>
+        <label>Confirm new password
+            <input
+                type="password"
+                placeholder="Type the new password again"
+                name="new_password_check"
+            >
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+            token = escape_html(&parameters.reset_token),
+        )))
+}
+
+/// Sets the new password and logs the user out of every session: whoever knew the old password
+/// is locked out.
+#[tracing::instrument(name = "Reset a password", skip_all, fields(user_id=tracing::field::Empty))]
+#[post("/login/reset")]
+pub async fn reset_password(
+    parameters: web::Query<ResetParameters>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    breached_passwords: web::Data<BreachedPasswords>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let user_id = get_valid_reset_token(&mut transaction, &parameters.reset_token).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    // The token matches a stored one, it is safe to put back in a URL as is.
+    let form_url = format!("/login/reset?reset_token={}", parameters.reset_token);
+
+    let FormData {
+        new_password,
+        new_password_check,
+    } = form.0;
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&form_url));
+    }
+    let new_password = match NewPassword::parse(new_password, &breached_passwords) {
+        Ok(new_password) => new_password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&form_url));
+        }
+    };
+
+    let password_hash = hash_password(new_password.into(), &hashing).await?;
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the password of the user.")?;
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the password reset tokens of the user.")?;
+    revoke_all_sessions(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+/// Reset tokens are as good as a password for a while, only their hash is stored. They are
+/// random enough for a fast hash to be safe, unlike passwords.
+pub fn hash_reset_token(reset_token: &str) -> String {
+    format!("{:x}", Sha256::digest(reset_token.as_bytes()))
+}
+
+/// Looks a token up, locking it until the transaction ends so that it cannot be used twice.
+async fn get_valid_reset_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    reset_token: &str,
+) -> Result<Uuid, PasswordResetError> {
+    let token = sqlx::query!(
+        r#"
+        SELECT user_id, expires_at
+        FROM password_reset_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+        hash_reset_token(reset_token)
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the password reset token.")?
+    .ok_or(PasswordResetError::UnknownToken)?;
+    if token.expires_at < Utc::now() {
+        return Err(PasswordResetError::ExpiredToken);
+    }
+    Ok(token.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_reset_token;
+
+    #[test]
+    fn reset_tokens_are_stored_as_their_sha256_hex_digest() {
+        assert_eq!(
+            hash_reset_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use chrono::Utc;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{verify_second_factor, EncryptionKey, SecondFactor, SecondFactorCheck},
+    login_throttle::LoginThrottle,
+    session_state::{PendingSecondFactor, TypedSession},
+    utils::{e500, escape_html, see_other},
+};
+
+use super::post::start_session;
+
+/// How long after getting the password right the second factor can be entered.
+const SECOND_FACTOR_TIMEOUT_SECONDS: i64 = 5 * 60;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+#[get("/login/two_factor")]
+pub async fn two_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if get_pending_login(&session)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", escape_html(m.content())).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    <p>Enter the code from your authenticator app, or one of your recovery codes.</p>
+    <form action="/login/two_factor" method="post">
+        <label>Code
+            <input
+                type="text"
+                inputmode="numeric"
+                autocomplete="one-time-code"
+                placeholder="123456"
+                name="code"
+            >
+        </label>
+        <button type="submit">Verify</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "Verify the second factor of a login",
+    skip_all,
+    fields(user_id=tracing::field::Empty)
+)]
+#[post("/login/two_factor")]
+pub async fn verify_two_factor(
+    form: web::Form<FormData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    encryption_key: web::Data<EncryptionKey>,
+    request: HttpRequest,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pending = match get_pending_login(&session)? {
+        Some(pending) => pending,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&pending.user_id));
+    // Every code counts as an attempt for the throttle, like a password would.
+    let client_address = login_throttle.client_address(&request);
+    let attempt = match login_throttle
+        .reserve_attempt(&pending.username, client_address.as_deref())
+        .await
+        .map_err(e500)?
+    {
+        Ok(attempt) => attempt,
+        Err(throttled) => {
+            FlashMessage::error(throttled.to_string()).send();
+            return Ok(see_other("/login/two_factor"));
+        }
+    };
+
+    match verify_second_factor(pending.user_id, &form.code, &encryption_key, &pool)
+        .await
+        .map_err(e500)?
+    {
+        SecondFactorCheck::Valid(second_factor) => {
+            login_throttle.record_success(attempt).await.map_err(e500)?;
+            session.renew();
+            start_session(&session, pending.user_id, &pool)
+                .await
+                .map_err(e500)?;
+            if let SecondFactor::RecoveryCode { remaining } = second_factor {
+                FlashMessage::warning(format!(
+                    "You logged in with a recovery code, {} left.",
+                    remaining
+                ))
+                .send();
+            }
+            Ok(see_other("/admin/dashboard"))
+        }
+        // Too many wrong codes: the login starts over from the password.
+        SecondFactorCheck::TooManyInvalid => {
+            session.remove_pending_second_factor();
+            FlashMessage::error("Too many invalid codes, please log in again.").send();
+            Ok(see_other("/login"))
+        }
+        SecondFactorCheck::Invalid => {
+            FlashMessage::error("The code is not valid, please try again.").send();
+            Ok(see_other("/login/two_factor"))
+        }
+    }
+}
+
+/// The login waiting for a second factor, unless there is none or it has timed out.
+fn get_pending_login(
+    session: &TypedSession,
+) -> Result<Option<PendingSecondFactor>, actix_web::Error> {
+    let pending = match session.get_pending_second_factor().map_err(e500)? {
+        Some(pending) => pending,
+        None => {
+            FlashMessage::error("Please log in first.").send();
+            return Ok(None);
+        }
+    };
+    if Utc::now().timestamp() - pending.started_at > SECOND_FACTOR_TIMEOUT_SECONDS {
+        session.remove_pending_second_factor();
+        FlashMessage::error("Your login has timed out, please log in again.").send();
+        return Ok(None);
+    }
+    Ok(Some(pending))
+}
@@ -1,15 +1,24 @@
+// Route handlers share their module's name, the glob re-exports below are what `startup` uses.
+#![allow(hidden_glob_reexports)]
+
 mod admin;
 mod health_check;
 mod home;
+mod invites;
 mod login;
 mod newsletter;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_privacy;
+mod subscriptions_unsubscribe;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
+pub use invites::*;
 pub use login::*;
 pub use newsletter::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_privacy::*;
+pub use subscriptions_unsubscribe::*;
@@ -1,9 +1,25 @@
-use actix_web::{post, web, HttpResponse, ResponseError};
+use actix_web::{
+    http::header::{self, HeaderMap, HeaderValue},
+    post, web, HttpRequest, HttpResponse, ResponseError,
+};
 use anyhow::Context;
+use base64::Engine;
+use chrono::Utc;
 use reqwest::StatusCode;
-use sqlx::PgPool;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    authentication::{
+        get_role, is_session_active, is_two_factor_enabled, validate_credentials, AuthError,
+        Credentials, PasswordHashing, Role,
+    },
+    domain::MergeTemplate,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    login_throttle::{LoginThrottle, Throttled},
+    session_state::TypedSession,
+};
 
 use super::error_chain_fmt;
 
@@ -21,6 +37,14 @@ pub struct Content {
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    Throttled(Throttled),
+    #[error("Your role ({0}) does not allow you to publish newsletter issues.")]
+    Forbidden(Role),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -34,68 +58,253 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::Throttled(_) => StatusCode::TOO_MANY_REQUESTS,
+            PublishError::Forbidden(_) => StatusCode::FORBIDDEN,
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = match self {
+            // Tell API clients what is wrong with their payload, e.g. which merge field.
+            PublishError::ValidationError(message) => {
+                HttpResponse::build(self.status_code()).body(message.clone())
+            }
+            _ => HttpResponse::new(self.status_code()),
+        };
+        match self {
+            PublishError::AuthError(_) => {
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+            }
+            PublishError::Throttled(throttled) => {
+                let header_value = HeaderValue::from(throttled.retry_after_seconds());
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, header_value);
+            }
+            _ => {}
+        }
+        response
+    }
 }
 
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
 #[post("/newsletters")]
 pub async fn publish_newsletter(
+    request: HttpRequest,
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    session: TypedSession,
+    login_throttle: web::Data<LoginThrottle>,
+    hashing: web::Data<PasswordHashing>,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    "Skipping confirmed subscriber. \
-                    Their stored contact details are invalid\n{}",
-                    error,
+    let user_id = authenticate(&request, &session, &pool, &login_throttle, &hashing).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let role = get_role(user_id, &pool).await?;
+    if role < Role::Editor {
+        return Err(PublishError::Forbidden(role));
+    }
+    let BodyData { title, content } = body.0;
+    let html_content = MergeTemplate::parse(content.html).map_err(PublishError::ValidationError)?;
+    let text_content = MergeTemplate::parse(content.text).map_err(PublishError::ValidationError)?;
+    let idempotency_key = get_idempotency_key(&request)?;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(&pool, idempotency_key, user_id).await? {
+            NextAction::StartProcessing(transaction) => transaction,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
+    };
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        text_content.as_ref(),
+        html_content.as_ref(),
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    let response = HttpResponse::Ok().finish();
+    let response = match idempotency_key {
+        Some(idempotency_key) => {
+            save_response(transaction, &idempotency_key, user_id, response).await?
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue.")?;
+            response
+        }
+    };
+    Ok(response)
+}
+
+/// Accepts either a logged-in admin session or HTTP Basic credentials, for users without
+/// two-factor authentication. Basic credentials go through the same throttle as the login form.
+async fn authenticate(
+    request: &HttpRequest,
+    session: &TypedSession,
+    pool: &PgPool,
+    login_throttle: &LoginThrottle,
+    hashing: &PasswordHashing,
+) -> Result<Uuid, PublishError> {
+    let user_id = session
+        .get_user_id()
+        .context("Failed to read the user id from the session.")?;
+    let session_id = session
+        .get_session_id()
+        .context("Failed to read the session id from the session.")?;
+    if let (Some(user_id), Some(session_id)) = (user_id, session_id) {
+        if is_session_active(user_id, session_id, pool).await? {
+            return Ok(user_id);
+        }
+    }
+
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    let username = credentials.username.clone();
+    let client_address = login_throttle.client_address(request);
+    tracing::Span::current().record("username", tracing::field::display(&username));
+    let attempt = match login_throttle
+        .reserve_attempt(&username, client_address.as_deref())
+        .await?
+    {
+        Ok(attempt) => attempt,
+        Err(throttled) => return Err(PublishError::Throttled(throttled)),
+    };
+    // A failed attempt has already been recorded as such.
+    let user_id = match validate_credentials(credentials, hashing, pool).await {
+        Ok(user_id) => user_id,
+        Err(e @ AuthError::InvalidCredentials(_)) => {
+            return Err(PublishError::AuthError(e.into()));
+        }
+        Err(e @ AuthError::UnexpectedError(_)) => {
+            return Err(PublishError::UnexpectedError(e.into()))
+        }
+    };
+    // A password alone must not get around the second factor.
+    if is_two_factor_enabled(user_id, pool).await? {
+        login_throttle.release(attempt).await?;
+        return Err(PublishError::AuthError(anyhow::anyhow!(
+            "Two-factor authentication is enabled, Basic credentials are not enough."
+        )));
+    }
+    login_throttle.record_success(attempt).await?;
+    Ok(user_id)
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // The header value, if present, must be a valid UTF8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimitator
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
+
+fn get_idempotency_key(request: &HttpRequest) -> Result<Option<IdempotencyKey>, PublishError> {
+    match request.headers().get("Idempotency-Key") {
+        Some(value) => {
+            let value = value.to_str().map_err(|_| {
+                PublishError::ValidationError(
+                    "The idempotency key must be a visible ASCII string".into(),
                 )
-            }
+            })?;
+            let idempotency_key = IdempotencyKey::try_from(value.to_owned())
+                .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+            Ok(Some(idempotency_key))
         }
+        None => Ok(None),
     }
-    Ok(HttpResponse::Ok().finish())
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+#[tracing::instrument(skip_all)]
+pub(crate) async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            has_merge_fields,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, true, $5)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(newsletter_issue_id)
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
+#[tracing::instrument(skip_all)]
+pub(crate) async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
         r#"
-        SELECT email
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
         FROM subscriptions
         WHERE status = 'confirmed'
-        "#
+        "#,
+        newsletter_issue_id,
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-
-    Ok(confirmed_subscribers)
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
@@ -8,10 +8,16 @@ use uuid::Uuid;
 
 use crate::{
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    email_outbox::enqueue_email,
+    email_templates::{ConfirmationEmail, EmailTemplate},
     startup::ApplicationBaseUrl,
 };
 
+use super::subscriptions_confirm::delete_tokens;
+
+/// How long a confirmation link stays valid after it has been sent.
+pub const SUBSCRIPTION_TOKEN_TTL_HOURS: i64 = 24;
+
 // to use Deserialize like this you have to enable the derive feature on serde.
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -57,7 +63,7 @@ impl ResponseError for SubscribeError {
 // Telemetry data lets us get better insight as too what is going on in our application.
 #[tracing::instrument(
     name= "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, base_url),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -69,20 +75,42 @@ pub async fn subscribe(
     // Retrieving a connection from the application state! this is the way `actix_web` handles
     // dependency injection
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
     // create a `new_subscriber` from teh incoming form
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
     // init the postgres transaction
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection")?;
-    // insert subscriber into the db.
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // insert subscriber into the db. A previous subscription with the same email is left alone,
+    // so that submitting the form twice, even concurrently, does not trip over the unique
+    // constraint.
+    let new_subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database.")?;
+    let subscriber_id = match new_subscriber_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let subscriber = get_existing_subscriber(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to look up an existing subscriber in the database.")?
+                .context("The existing subscriber was deleted while subscribing again.")?;
+            // Nothing to do, and the response must not tell whether the email is already
+            // subscribed.
+            if subscriber.status == "confirmed" {
+                return Ok(HttpResponse::Ok().finish());
+            }
+            // Pending subscribers get a fresh confirmation link, unsubscribed ones opt back in
+            // and confirm their email again. Either way, under the name they just gave.
+            restart_confirmation(&mut transaction, subscriber.id, &new_subscriber.name)
+                .await
+                .context("Failed to restart the confirmation of an existing subscriber.")?;
+            subscriber.id
+        }
+    };
 
     // generate a randoms subscription token.
     let subscription_token = generate_subscription_token();
@@ -91,52 +119,58 @@ pub async fn subscribe(
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
 
+    // queue the confirmation email: it only goes out if the subscriber is actually stored, and a
+    // flaky email provider does not fail the request.
+    enqueue_confirmation_email(
+        &mut transaction,
+        &new_subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to enqueue a confirmation email.")?;
+
     // commit the transation to the db.
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
-    // send confirmation_link email to the new subscriber
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email.")?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
 #[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url)
+    name = "Enqueue a confirmation email to a new subscriber",
+    skip(transaction, new_subscriber, base_url)
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to out newsletter!<br />\
-                Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+    let email = ConfirmationEmail {
+        name: new_subscriber.name.as_ref(),
+        confirmation_link: &confirmation_link,
+    }
+    .render()
+    .context("Failed to render the confirmation email.")?;
+    enqueue_email(
+        transaction,
+        &new_subscriber.email,
+        "Welcome!",
+        &email.html,
+        &email.text,
+    )
+    .await
+    .context("Failed to store the confirmation email in the outbox.")
 }
 
+/// Returns `None`, without inserting anything, if the email is already subscribed.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(transaction, new_subscriber)
@@ -144,23 +178,65 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = sqlx::query_scalar!(
         r#"
-            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-            VALUES ($1, $2, $3, $4, 'pending_confirmation')
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+            VALUES ($1, $2, $3, $4, 'pending_confirmation', $5)
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        generate_subscription_token()
     )
-    .execute(transaction)
+    .fetch_optional(transaction)
     .await?;
     Ok(subscriber_id)
 }
 
+pub struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+#[tracing::instrument(name = "Get an existing subscriber by email", skip_all)]
+pub async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref()
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+/// Puts the subscriber back to `pending_confirmation` under `name`, the one the new
+/// confirmation email greets them with, and invalidates the links sent so far.
+#[tracing::instrument(
+    name = "Restart the confirmation of a subscriber",
+    skip(transaction, name)
+)]
+pub(crate) async fn restart_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'pending_confirmation', name = $2 WHERE id = $1"#,
+        subscriber_id,
+        name.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    delete_tokens(transaction, subscriber_id).await
+}
+
 pub struct StoreTokenError(sqlx::Error);
 
 impl std::fmt::Display for StoreTokenError {
@@ -194,11 +270,15 @@ pub async fn store_token(
     subscriber_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
+    let created_at = Utc::now();
+    let expires_at = created_at + chrono::Duration::hours(SUBSCRIPTION_TOKEN_TTL_HOURS);
     sqlx::query!(
-        r#"INSERT INTO subscriptions_tokens (subscription_token, subscriber_id)
-           VALUES ($1, $2)"#,
+        r#"INSERT INTO subscriptions_tokens (subscription_token, subscriber_id, created_at, expires_at)
+           VALUES ($1, $2, $3, $4)"#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        created_at,
+        expires_at
     )
     .execute(transaction)
     .await
@@ -206,7 +286,7 @@ pub async fn store_token(
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
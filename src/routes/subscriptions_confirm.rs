@@ -1,7 +1,8 @@
 use actix_web::{get, web, HttpResponse, ResponseError};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use super::error_chain_fmt;
@@ -17,6 +18,8 @@ pub enum ConfirmationError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("There is no subscriber associated with the provided token.")]
     UnknownToken,
+    #[error("The confirmation link has expired, please subscribe again.")]
+    ExpiredToken,
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -29,6 +32,7 @@ impl ResponseError for ConfirmationError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
             Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -42,26 +46,44 @@ pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = get_token(&mut transaction, &parameters.subscription_token)
         .await
         .context("Failed to retrieve subscriber id associated with the provided token.")?
         .ok_or(ConfirmationError::UnknownToken)?;
-    confirm_subscriber(&pool, subscriber_id)
+    if token.expires_at <= Utc::now() {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    confirm_subscriber(&mut transaction, token.subscriber_id)
         .await
         .context("Failed to update the subscriber status to `confirmed`.")?;
+    // Tokens are single-use: once the subscription is confirmed the link stops working.
+    delete_tokens(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to delete the subscription tokens of a confirmed subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
     Ok(HttpResponse::Ok().finish())
 }
 
 #[tracing::instrument(
     name="Mark subscriber as confirmed"
-    skip(subscriber_id, pool)
+    skip(subscriber_id, transaction)
 )]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query:  {:?}", e);
@@ -70,21 +92,47 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()
     Ok(())
 }
 
-//
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+pub struct SubscriptionToken {
+    subscriber_id: Uuid,
+    expires_at: DateTime<Utc>,
+}
+
+/// Looks a token up, locking it until the transaction ends so that two concurrent confirmations
+/// cannot both use it.
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, transaction))]
+pub async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscriptions_tokens WHERE subscription_token = $1"#,
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscriber_id, expires_at
+        FROM subscriptions_tokens
+        WHERE subscription_token = $1
+        FOR UPDATE
+        "#,
         subscription_token
     )
-    .fetch_optional(pool)
+    .fetch_optional(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result)
+}
+
+#[tracing::instrument(name = "Delete subscription tokens", skip(transaction))]
+pub(crate) async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscriptions_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
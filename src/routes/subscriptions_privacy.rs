@@ -0,0 +1,572 @@
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    post, web, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_outbox::enqueue_email,
+    email_templates::{DataRequestEmail, EmailTemplate},
+    startup::ApplicationBaseUrl,
+    utils::escape_html,
+};
+
+use super::{
+    error_chain_fmt, subscriptions::generate_subscription_token,
+    subscriptions_unsubscribe::cancel_pending_deliveries,
+};
+
+/// How long the link to confirm a data request stays valid after it has been sent.
+pub const DATA_REQUEST_TTL_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DataRequestKind {
+    Export,
+    Erasure,
+}
+
+impl DataRequestKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DataRequestKind::Export => "export",
+            DataRequestKind::Erasure => "erasure",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "export" => Ok(DataRequestKind::Export),
+            "erasure" => Ok(DataRequestKind::Erasure),
+            other => Err(anyhow::anyhow!("Unknown data request kind: {}", other)),
+        }
+    }
+}
+
+/// Who asked for a subscriber to be erased, recorded in the audit log.
+#[derive(Clone, Copy, Debug)]
+pub enum ErasureRequester {
+    Subscriber,
+    Admin,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+    request: DataRequestKind,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestParameters {
+    request_token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum DataRequestError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no data request associated with the provided token.")]
+    UnknownToken,
+    #[error("The link has expired, please make a new request.")]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for DataRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[get("/subscriptions/privacy")]
+pub async fn data_request_form() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Your data</title>
+    </head>
+    <body>
+        <form action="/subscriptions/privacy" method="post">
+            <label>Email
+                <input type="email" placeholder="Enter your email" name="email">
+            </label>
+            <br>
+            <label>
+                <input type="radio" name="request" value="export" checked>
+                Send me a copy of my data
+            </label>
+            <br>
+            <label>
+                <input type="radio" name="request" value="erasure">
+                Delete my data
+            </label>
+            <br>
+            <button type="submit">Send request</button>
+        </form>
+    </body>
+    </html>
+              "#,
+    )
+}
+
+/// Emails a verification link to the subscriber, if there is one.
+///
+/// The response is the same either way, so that it cannot be used to find out who is subscribed.
+#[tracing::instrument(
+    name = "Request a subscriber data export or erasure",
+    skip(form, pool, base_url),
+    fields(request = form.request.as_str())
+)]
+#[post("/subscriptions/privacy")]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestFormData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, DataRequestError> {
+    let DataRequestFormData { email, request } = form.0;
+    let email = SubscriberEmail::parse(email).map_err(DataRequestError::ValidationError)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber = sqlx::query!(
+        r#"SELECT id, name FROM subscriptions WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    if let Some(subscriber) = subscriber {
+        let request_token = generate_subscription_token();
+        store_data_request(&mut transaction, subscriber.id, request, &request_token)
+            .await
+            .context("Failed to store the data request.")?;
+        let link = format!(
+            "{}/subscriptions/privacy/confirm?request_token={}",
+            base_url.0, request_token
+        );
+        let rendered = DataRequestEmail {
+            name: &subscriber.name,
+            request: match request {
+                DataRequestKind::Export => "a copy of your data",
+                DataRequestKind::Erasure => "the deletion of your data",
+            },
+            link: &link,
+            expires_in_hours: DATA_REQUEST_TTL_HOURS,
+        }
+        .render()
+        .context("Failed to render the data request email.")?;
+        enqueue_email(
+            &mut transaction,
+            &email,
+            "Your data request",
+            &rendered.html,
+            &rendered.text,
+        )
+        .await
+        .context("Failed to store the data request email in the outbox.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a data request.")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Your data</title>
+    </head>
+    <body>
+        <p>If this email is subscribed to our newsletter, it will receive a link to confirm your request.</p>
+    </body>
+    </html>
+              "#,
+    ))
+}
+
+/// The page behind the emailed link. As for unsubscribing, nothing happens until the form is
+/// submitted: link scanners issue `GET` requests on their own.
+#[tracing::instrument(name = "Show a data request confirmation page", skip_all)]
+#[get("/subscriptions/privacy/confirm")]
+pub async fn data_request_confirmation_form(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataRequestError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let request = get_valid_data_request(&mut transaction, &parameters.request_token).await?;
+    let (explanation, button) = match request.kind {
+        DataRequestKind::Export => (
+            "Download a copy of the data we hold about you, as JSON.",
+            "Download my data",
+        ),
+        DataRequestKind::Erasure => (
+            "Delete all the data we hold about you. You will stop receiving our newsletter, this cannot be undone.",
+            "Delete my data",
+        ),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Your data</title>
+    </head>
+    <body>
+        <p>{explanation}</p>
+        <form
+            action="/subscriptions/privacy/confirm?request_token={token}"
+            method="post"
+        >
+            <button type="submit">{button}</button>
+        </form>
+    </body>
+    </html>
+              "#,
+            token = escape_html(&parameters.request_token),
+        )))
+}
+
+#[tracing::instrument(name = "Fulfil a data request", skip_all)]
+#[post("/subscriptions/privacy/confirm")]
+pub async fn fulfil_data_request(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataRequestError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let request = get_valid_data_request(&mut transaction, &parameters.request_token).await?;
+    let response = match request.kind {
+        DataRequestKind::Export => {
+            let export = export_subscriber_data(&mut transaction, request.subscriber_id).await?;
+            // Links are single use, like confirmation links.
+            sqlx::query!(
+                r#"DELETE FROM subscriber_data_requests WHERE request_token = $1"#,
+                parameters.request_token
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to delete a fulfilled data request.")?;
+            HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .insert_header(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename("my-data.json".into())],
+                })
+                .body(export.to_string())
+        }
+        DataRequestKind::Erasure => {
+            erase_subscriber(
+                &mut transaction,
+                request.subscriber_id,
+                ErasureRequester::Subscriber,
+            )
+            .await?;
+            HttpResponse::Ok().content_type(ContentType::html()).body(
+                r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Your data</title>
+    </head>
+    <body>
+        <p>Your data has been deleted.</p>
+    </body>
+    </html>
+              "#,
+            )
+        }
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to fulfil a data request.")?;
+    Ok(response)
+}
+
+#[tracing::instrument(skip(transaction, request_token))]
+async fn store_data_request(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kind: DataRequestKind,
+    request_token: &str,
+) -> Result<(), sqlx::Error> {
+    let created_at = Utc::now();
+    let expires_at = created_at + chrono::Duration::hours(DATA_REQUEST_TTL_HOURS);
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_data_requests
+            (request_token, subscriber_id, kind, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        request_token,
+        subscriber_id,
+        kind.as_str(),
+        created_at,
+        expires_at
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct DataRequest {
+    subscriber_id: Uuid,
+    kind: DataRequestKind,
+}
+
+/// Looks a request up, locking it until the transaction ends so that it cannot be used twice.
+async fn get_valid_data_request(
+    transaction: &mut Transaction<'_, Postgres>,
+    request_token: &str,
+) -> Result<DataRequest, DataRequestError> {
+    let request = sqlx::query!(
+        r#"
+        SELECT subscriber_id, kind, expires_at
+        FROM subscriber_data_requests
+        WHERE request_token = $1
+        FOR UPDATE
+        "#,
+        request_token
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the data request associated with the provided token.")?
+    .ok_or(DataRequestError::UnknownToken)?;
+    if request.expires_at < Utc::now() {
+        return Err(DataRequestError::ExpiredToken);
+    }
+    Ok(DataRequest {
+        subscriber_id: request.subscriber_id,
+        kind: DataRequestKind::parse(&request.kind)?,
+    })
+}
+
+/// Every row that references the subscriber, grouped by table.
+#[tracing::instrument(skip(transaction))]
+async fn export_subscriber_data(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let subscription = sqlx::query!(
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to retrieve the subscription.")?;
+    let tokens = sqlx::query!(
+        r#"
+        SELECT created_at, expires_at
+        FROM subscriptions_tokens
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve the confirmation tokens.")?;
+    let data_requests = sqlx::query!(
+        r#"
+        SELECT kind, created_at, expires_at
+        FROM subscriber_data_requests
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve the data requests.")?;
+    let pending_deliveries = sqlx::query!(
+        r#"
+        SELECT i.title, q.n_retries, q.execute_after
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.subscriber_email = $1
+        "#,
+        subscription.email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve the pending deliveries.")?;
+    let failed_deliveries = sqlx::query!(
+        r#"
+        SELECT i.title, d.n_attempts, d.last_error, d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_email = $1
+        "#,
+        subscription.email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve the failed deliveries.")?;
+    let pending_emails = sqlx::query!(
+        r#"
+        SELECT subject, created_at
+        FROM email_outbox
+        WHERE recipient = $1
+        "#,
+        subscription.email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve the pending emails.")?;
+
+    Ok(serde_json::json!({
+        "subscription": {
+            "id": subscription.id,
+            "email": subscription.email,
+            "name": subscription.name,
+            "status": subscription.status,
+            "subscribed_at": timestamp(subscription.subscribed_at),
+        },
+        "confirmation_tokens": tokens.iter().map(|t| serde_json::json!({
+            "created_at": timestamp(t.created_at),
+            "expires_at": timestamp(t.expires_at),
+        })).collect::<Vec<_>>(),
+        "data_requests": data_requests.iter().map(|r| serde_json::json!({
+            "kind": r.kind,
+            "created_at": timestamp(r.created_at),
+            "expires_at": timestamp(r.expires_at),
+        })).collect::<Vec<_>>(),
+        "pending_deliveries": pending_deliveries.iter().map(|d| serde_json::json!({
+            "newsletter_issue": d.title,
+            "n_retries": d.n_retries,
+            "execute_after": timestamp(d.execute_after),
+        })).collect::<Vec<_>>(),
+        "failed_deliveries": failed_deliveries.iter().map(|d| serde_json::json!({
+            "newsletter_issue": d.title,
+            "n_attempts": d.n_attempts,
+            "last_error": d.last_error,
+            "failed_at": timestamp(d.failed_at),
+        })).collect::<Vec<_>>(),
+        "pending_emails": pending_emails.iter().map(|e| serde_json::json!({
+            "subject": e.subject,
+            "created_at": timestamp(e.created_at),
+        })).collect::<Vec<_>>(),
+    }))
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.to_rfc3339()
+}
+
+/// Deletes the subscriber and every row referencing them, leaving an anonymised entry in
+/// `subscriber_erasures`.
+///
+/// Returns the email of the erased subscriber, or `None` if there is no such subscriber.
+#[tracing::instrument(skip(transaction))]
+pub(crate) async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    requested_by: ErasureRequester,
+) -> Result<Option<String>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"SELECT email, status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(None);
+    };
+    let n_tokens = sqlx::query!(
+        r#"DELETE FROM subscriptions_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the confirmation tokens of the subscriber.")?
+    .rows_affected();
+    let n_pending_deliveries = cancel_pending_deliveries(transaction, &subscriber.email)
+        .await
+        .context("Failed to cancel the pending deliveries of the subscriber.")?;
+    let n_failed_deliveries = sqlx::query!(
+        r#"DELETE FROM issue_delivery_dead_letters WHERE subscriber_email = $1"#,
+        subscriber.email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the failed deliveries of the subscriber.")?
+    .rows_affected();
+    let n_pending_emails = sqlx::query!(
+        r#"DELETE FROM email_outbox WHERE recipient = $1"#,
+        subscriber.email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the emails waiting to be sent to the subscriber.")?
+    .rows_affected();
+    // Data requests go along with the subscription (`ON DELETE CASCADE`).
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the subscriber.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_erasures (
+            erasure_id,
+            erased_at,
+            requested_by,
+            subscription_status,
+            n_tokens,
+            n_pending_deliveries,
+            n_failed_deliveries,
+            n_pending_emails
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        Utc::now(),
+        match requested_by {
+            ErasureRequester::Subscriber => "subscriber",
+            ErasureRequester::Admin => "admin",
+        },
+        subscriber.status,
+        n_tokens as i64,
+        n_pending_deliveries as i64,
+        n_failed_deliveries as i64,
+        n_pending_emails as i64
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the erasure in the audit log.")?;
+    Ok(Some(subscriber.email))
+}
@@ -0,0 +1,168 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    unsubscribe_token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The page behind the unsubscribe link of our emails.
+///
+/// It does not unsubscribe anybody by itself: link scanners and mail previews issue `GET`
+/// requests on their own, so the subscriber has to confirm by submitting the form.
+#[tracing::instrument(name = "Show the unsubscribe page", skip(parameters, pool))]
+#[get("/subscriptions/unsubscribe")]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    get_subscriber_id_from_unsubscribe_token(&pool, &parameters.unsubscribe_token)
+        .await
+        .context("Failed to retrieve the subscriber id associated with the provided token.")?
+        .ok_or(UnsubscribeError::UnknownToken)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Unsubscribe</title>
+    </head>
+    <body>
+        <p>Do you want to stop receiving our newsletter?</p>
+        <form
+            action="/subscriptions/unsubscribe?unsubscribe_token={}"
+            method="post"
+        >
+            <button type="submit">Unsubscribe</button>
+        </form>
+    </body>
+    </html>
+              "#,
+            parameters.unsubscribe_token
+        )))
+}
+
+/// Handles both the form above and RFC 8058 one-click requests sent by mail clients, whose body
+/// (`List-Unsubscribe=One-Click`) carries no information we need.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool))]
+#[post("/subscriptions/unsubscribe")]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_email =
+        mark_subscriber_as_unsubscribed(&mut transaction, &parameters.unsubscribe_token)
+            .await
+            .context("Failed to update the subscriber status to `unsubscribed`.")?
+            .ok_or(UnsubscribeError::UnknownToken)?;
+    cancel_pending_deliveries(&mut transaction, &subscriber_email)
+        .await
+        .context("Failed to cancel the pending deliveries of an unsubscribed subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"
+<!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Unsubscribed</title>
+    </head>
+    <body>
+        <p>You have been unsubscribed, you will not receive any more emails from us.</p>
+    </body>
+    </html>
+              "#,
+    ))
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from unsubscribe token",
+    skip(unsubscribe_token, pool)
+)]
+async fn get_subscriber_id_from_unsubscribe_token(
+    pool: &PgPool,
+    unsubscribe_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE unsubscribe_token = $1"#,
+        unsubscribe_token
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+/// Returns the email of the subscriber, or `None` if the token is unknown.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip_all)]
+async fn mark_subscriber_as_unsubscribed(
+    transaction: &mut Transaction<'_, Postgres>,
+    unsubscribe_token: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed'
+        WHERE unsubscribe_token = $1
+        RETURNING email
+        "#,
+        unsubscribe_token
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(result.map(|r| r.email))
+}
+
+/// Issues that are still being sent out must not reach the subscriber anymore.
+///
+/// Returns the number of cancelled deliveries.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn cancel_pending_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_email: &str,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        subscriber_email
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected())
+}
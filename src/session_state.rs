@@ -6,8 +6,21 @@ use uuid::Uuid;
 
 pub struct TypedSession(Session);
 
+/// A login that got the password right and is waiting for the second factor. The user id is only
+/// inserted in the session once the second factor has been verified.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct PendingSecondFactor {
+    pub user_id: Uuid,
+    /// As typed in the login form, failed attempts are throttled per username.
+    pub username: String,
+    /// Unix timestamp of the moment the password was verified.
+    pub started_at: i64,
+}
+
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const PENDING_SECOND_FACTOR_KEY: &'static str = "pending_second_factor";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -20,6 +33,36 @@ impl TypedSession {
     pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
+
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    pub fn insert_pending_second_factor(
+        &self,
+        pending: &PendingSecondFactor,
+    ) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_SECOND_FACTOR_KEY, pending)
+    }
+
+    pub fn get_pending_second_factor(
+        &self,
+    ) -> Result<Option<PendingSecondFactor>, SessionGetError> {
+        self.0.get(Self::PENDING_SECOND_FACTOR_KEY)
+    }
+
+    pub fn remove_pending_second_factor(&self) {
+        self.0.remove(Self::PENDING_SECOND_FACTOR_KEY);
+    }
+
+    /// Removes the session state from the store and clears the session cookie.
+    pub fn log_out(self) {
+        self.0.purge()
+    }
 }
 
 impl FromRequest for TypedSession {
@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use actix_session::storage::{
+    LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+use anyhow::Context;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use secrecy::{ExposeSecret, Secret};
+
+type SessionState = HashMap<String, String>;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    #[default]
+    Redis,
+    Memory,
+}
+
+/// The session storage backend used by `SessionMiddleware`.
+///
+/// Production deployments keep their sessions in Redis, while tests and local experiments can
+/// use an in-process store so that they do not depend on a running Redis instance.
+#[derive(Clone)]
+pub enum SessionBackend {
+    Redis(RedisSessionStore),
+    Memory(MemorySessionStore),
+}
+
+impl SessionBackend {
+    pub async fn build(
+        kind: SessionStoreKind,
+        redis_uri: &Secret<String>,
+    ) -> Result<Self, anyhow::Error> {
+        match kind {
+            SessionStoreKind::Redis => {
+                let store = RedisSessionStore::new(redis_uri.expose_secret())
+                    .await
+                    .context("Failed to connect to the Redis session store.")?;
+                Ok(Self::Redis(store))
+            }
+            SessionStoreKind::Memory => Ok(Self::Memory(MemorySessionStore::default())),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for SessionBackend {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Redis(store) => store.load(session_key).await,
+            Self::Memory(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Redis(store) => store.save(session_state, ttl).await,
+            Self::Memory(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Redis(store) => store.update(session_key, session_state, ttl).await,
+            Self::Memory(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redis(store) => store.update_ttl(session_key, ttl).await,
+            Self::Memory(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redis(store) => store.delete(session_key).await,
+            Self::Memory(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+/// An in-process session store, shared by every worker of the same `HttpServer`.
+///
+/// Sessions are lost when the process exits: do not use it in production.
+#[derive(Clone, Default)]
+pub struct MemorySessionStore {
+    sessions: Arc<Mutex<HashMap<String, (SessionState, OffsetDateTime)>>>,
+}
+
+impl MemorySessionStore {
+    fn insert(&self, session_key: &str, session_state: SessionState, ttl: &Duration) {
+        let expires_at = OffsetDateTime::now_utc() + *ttl;
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session_key.to_owned(), (session_state, expires_at));
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(session_key.as_ref()) {
+            Some((_, expires_at)) if *expires_at <= OffsetDateTime::now_utc() => {
+                sessions.remove(session_key.as_ref());
+                Ok(None)
+            }
+            Some((session_state, _)) => Ok(Some(session_state.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        self.insert(&session_key, session_state, ttl);
+        session_key
+            .try_into()
+            .map_err(|e| SaveError::Other(anyhow::Error::new(e)))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.insert(session_key.as_ref(), session_state, ttl);
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        if let Some((_, expires_at)) = self.sessions.lock().unwrap().get_mut(session_key.as_ref()) {
+            *expires_at = OffsetDateTime::now_utc() + *ttl;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.sessions.lock().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
+
+fn generate_session_key() -> String {
+    std::iter::repeat_with(|| OsRng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect()
+}
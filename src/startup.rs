@@ -1,14 +1,40 @@
 use crate::{
+    authentication::{
+        reject_anonymous_users, BreachedPasswords, EncryptionKey, PasswordHashing,
+        SESSION_TTL_HOURS,
+    },
     configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
+    email_client::EmailSender,
+    login_throttle::{LoginAttemptStore, LoginThrottle},
     routes::{
-        change_password, change_password_form, confirm, health_check, home, login, login_form,
-        publish_newsletter, subscribe,
+        accept_invite, accept_invite_form, admin_dashboard, change_password, change_password_form,
+        change_user_role, confirm, confirm_subscriber_manually, confirm_two_factor,
+        data_request_confirmation_form, data_request_form, delete_subscriber,
+        disable_two_factor_authentication, enrol_two_factor, export_subscribers, failed_deliveries,
+        forgot_password_form, fulfil_data_request, health_check, home, import_subscribers,
+        import_subscribers_form, invite_user, list_subscribers, list_users, log_out,
+        log_out_other_sessions, login, login_form, preview_email_template, publish_newsletter,
+        publish_newsletter_form, publish_newsletter_issue, remove_user, request_password_reset,
+        request_subscriber_data, requeue_failed_delivery, resend_confirmation, reset_password,
+        reset_password_form, revoke_invite, subscribe, two_factor_form, two_factor_settings,
+        unsubscribe, unsubscribe_form, unsubscribe_subscriber, verify_two_factor,
     },
+    session_store::SessionBackend,
+    telemetry::AppRootSpanBuilder,
+};
+use actix_session::{config::BrowserSession, SessionMiddleware};
+use actix_web::{
+    cookie::{time::Duration, Key},
+    dev::Server,
+    web,
+    web::Data,
+    App, HttpServer,
 };
-use actix_web::{dev::Server, web::Data, App, HttpServer};
+use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
+use actix_web_lab::middleware::from_fn;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::net::TcpListener;
+use std::{net::TcpListener, sync::Arc};
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
@@ -17,28 +43,28 @@ pub struct Application {
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = configuration.email_client.client()?;
+        let encryption_key = EncryptionKey::parse(&configuration.application.encryption_key)?;
+        let password_hashing = PasswordHashing::new(&configuration.password_hashing)?;
+        let breached_passwords =
+            BreachedPasswords::load(configuration.breached_passwords_file.as_deref())?;
 
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
 
+        let session_store =
+            SessionBackend::build(configuration.session_store, &configuration.redis_uri).await?;
+
+        let login_throttle = LoginThrottle::new(
+            LoginAttemptStore::build(configuration.login_throttle.store, &connection_pool),
+            configuration.login_throttle,
+        );
+
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
         let server = run(
@@ -46,6 +72,12 @@ impl Application {
             connection_pool,
             email_client,
             configuration.application.base_url,
+            configuration.application.hmac_secret,
+            encryption_key,
+            session_store,
+            login_throttle,
+            password_hashing,
+            breached_passwords,
         )?;
 
         Ok(Self { port, server })
@@ -67,16 +99,31 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 
 pub struct ApplicationBaseUrl(pub String);
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client: Arc<dyn EmailSender>,
     base_url: String,
+    hmac_secret: Secret<String>,
+    encryption_key: EncryptionKey,
+    session_store: SessionBackend,
+    login_throttle: LoginThrottle,
+    password_hashing: PasswordHashing,
+    breached_passwords: BreachedPasswords,
 ) -> Result<Server, std::io::Error> {
     // Wrap the connection in a smart pointer
     let db_pool = Data::new(db_pool);
-    let email_client = Data::new(email_client);
+    let email_client: Data<dyn EmailSender> = Data::from(email_client);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let encryption_key = Data::new(encryption_key);
+    let login_throttle = Data::new(login_throttle);
+    let password_hashing = Data::new(password_hashing);
+    let breached_passwords = Data::new(breached_passwords);
+    // The same key signs the flash message cookies and the session cookie.
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
     // Capture `connection` from the surrounding environment using `move`
     // HttpServer handles all transport level concerns using a tcp connection that is listening to
     // incoming connections.
@@ -88,20 +135,75 @@ pub fn run(
         // `App` is where the application logic is defined, (i.e. what do when a connection hits a
         // certain route, what middle wares to use and how to handelr requests
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(message_framework.clone())
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .session_lifecycle(
+                        BrowserSession::default().state_ttl(Duration::hours(SESSION_TTL_HOURS)),
+                    )
+                    .build(),
+            )
+            .wrap(TracingLogger::<AppRootSpanBuilder>::new())
             .service(health_check)
             .service(home)
-            .service(change_password)
-            .service(change_password_form)
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .service(admin_dashboard)
+                    .service(change_password)
+                    .service(change_password_form)
+                    .service(publish_newsletter_form)
+                    .service(publish_newsletter_issue)
+                    .service(failed_deliveries)
+                    .service(requeue_failed_delivery)
+                    .service(preview_email_template)
+                    .service(list_subscribers)
+                    .service(export_subscribers)
+                    .service(import_subscribers_form)
+                    .service(import_subscribers)
+                    .service(resend_confirmation)
+                    .service(confirm_subscriber_manually)
+                    .service(unsubscribe_subscriber)
+                    .service(delete_subscriber)
+                    .service(list_users)
+                    .service(invite_user)
+                    .service(revoke_invite)
+                    .service(change_user_role)
+                    .service(remove_user)
+                    .service(two_factor_settings)
+                    .service(enrol_two_factor)
+                    .service(confirm_two_factor)
+                    .service(disable_two_factor_authentication)
+                    .service(log_out)
+                    .service(log_out_other_sessions),
+            )
             .service(login_form)
             .service(login)
+            .service(two_factor_form)
+            .service(verify_two_factor)
+            .service(forgot_password_form)
+            .service(request_password_reset)
+            .service(reset_password_form)
+            .service(reset_password)
             .service(subscribe)
             .service(confirm)
+            .service(unsubscribe_form)
+            .service(unsubscribe)
+            .service(data_request_form)
+            .service(request_subscriber_data)
+            .service(data_request_confirmation_form)
+            .service(fulfil_data_request)
+            .service(accept_invite_form)
+            .service(accept_invite)
             .service(publish_newsletter)
             // Get a pointer copy and attach it to the application state
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(encryption_key.clone())
+            .app_data(login_throttle.clone())
+            .app_data(password_hashing.clone())
+            .app_data(breached_passwords.clone())
     })
     // .bind(address) -- this uses a hard coded address
     .listen(listener)?
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::{
+    authentication::delete_expired_sessions, configuration::Settings,
+    idempotency::delete_expired_idempotency_keys, login_throttle::delete_old_login_attempts,
+    startup::get_connection_pool,
+};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct CleanupOutcome {
+    pub n_expired_tokens: u64,
+    pub n_unconfirmed_subscribers: u64,
+}
+
+pub async fn run_cleanup_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    loop {
+        // Failures are recorded by the spans of the cleanup functions, we try again at the next
+        // round.
+        let _ = delete_expired_subscriptions(&connection_pool).await;
+        let _ = delete_expired_sessions(&connection_pool).await;
+        let _ = delete_expired_idempotency_keys(&connection_pool).await;
+        let _ = delete_old_login_attempts(&connection_pool, &configuration.login_throttle).await;
+        let _ = delete_expired_password_reset_tokens(&connection_pool).await;
+        let _ = delete_expired_user_invites(&connection_pool).await;
+        let _ = delete_expired_data_requests(&connection_pool).await;
+        tokio::time::sleep(CLEANUP_INTERVAL).await;
+    }
+}
+
+/// Deletes expired confirmation tokens, then the subscribers left pending without any valid
+/// token: they never confirmed and no longer can.
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_expired_subscriptions(pool: &PgPool) -> Result<CleanupOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_expired_tokens =
+        sqlx::query!(r#"DELETE FROM subscriptions_tokens WHERE expires_at <= now()"#)
+            .execute(&mut transaction)
+            .await?
+            .rows_affected();
+    let n_unconfirmed_subscribers = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions s
+        WHERE
+            s.status = 'pending_confirmation' AND
+            NOT EXISTS (
+                SELECT 1 FROM subscriptions_tokens t WHERE t.subscriber_id = s.id
+            )
+        "#
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+    tracing::info!(
+        n_expired_tokens,
+        n_unconfirmed_subscribers,
+        "Cleaned up expired subscriptions."
+    );
+    Ok(CleanupOutcome {
+        n_expired_tokens,
+        n_unconfirmed_subscribers,
+    })
+}
+
+/// Deletes the password reset links that have expired without being used.
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_expired_password_reset_tokens(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_expired_reset_tokens =
+        sqlx::query!(r#"DELETE FROM password_reset_tokens WHERE expires_at <= now()"#)
+            .execute(pool)
+            .await?
+            .rows_affected();
+    tracing::info!(
+        n_expired_reset_tokens,
+        "Cleaned up expired password reset tokens."
+    );
+    Ok(n_expired_reset_tokens)
+}
+
+/// Deletes the invites that have expired without being accepted.
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_expired_user_invites(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_expired_invites = sqlx::query!(r#"DELETE FROM user_invites WHERE expires_at <= now()"#)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    tracing::info!(n_expired_invites, "Cleaned up expired user invites.");
+    Ok(n_expired_invites)
+}
+
+/// Deletes the export and erasure requests that have expired without being confirmed.
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_expired_data_requests(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_expired_data_requests =
+        sqlx::query!(r#"DELETE FROM subscriber_data_requests WHERE expires_at <= now()"#)
+            .execute(pool)
+            .await?
+            .rows_affected();
+    tracing::info!(n_expired_data_requests, "Cleaned up expired data requests.");
+    Ok(n_expired_data_requests)
+}
@@ -1,5 +1,10 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+};
 use tokio::task::JoinHandle;
-use tracing::{subscriber::set_global_default, Subscriber};
+use tracing::{subscriber::set_global_default, Span, Subscriber};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
@@ -48,3 +53,22 @@ where
     let current_span = tracing::Span::current();
     actix_web::rt::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+/// Root span builder for `TracingLogger`.
+///
+/// It extends the default root span with a `user_id` field, filled in by the admin authentication
+/// middleware once the user has been identified.
+pub struct AppRootSpanBuilder;
+
+impl RootSpanBuilder for AppRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        tracing_actix_web::root_span!(request, user_id = tracing::field::Empty)
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
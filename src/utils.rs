@@ -12,3 +12,15 @@ pub fn see_other(location: &str) -> HttpResponse {
         .insert_header((LOCATION, location))
         .finish()
 }
+
+pub fn e400<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
+/// Escapes a value before embedding it in an HTML page.
+pub fn escape_html(s: &str) -> String {
+    askama::MarkupDisplay::new_unsafe(s, askama::Html).to_string()
+}
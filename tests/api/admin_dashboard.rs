@@ -0,0 +1,135 @@
+use zero2prod::authentication::delete_expired_sessions;
+
+use crate::helpers::{assert_is_redirect_to, build_api_client, spawn_app, TestUser};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn anonymous_users_are_told_to_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Try to access an admin page
+    let response = app.get_change_password().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You must be logged in to access this page.</i></p>"));
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    // Act - Part 3 - Logout
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 4 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    // Act - Part 5 - Attempt to load admin panel
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logging_out_other_sessions_invalidates_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_client = build_api_client();
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    app.post_login(&login_body).await;
+    other_client
+        .post(format!("{}/login", &app.address))
+        .form(&login_body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Act - Part 1 - Log out the other sessions
+    let response = app.post_logout_other_sessions().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("<p><i>You have logged out of 1 other session(s).</i></p>"));
+
+    // Act - Part 3 - The other session is no longer valid
+    let response = other_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn expired_sessions_are_rejected_and_pruned() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!("UPDATE user_sessions SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - The session is no longer valid
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Its row is pruned
+    let n_expired_sessions = delete_expired_sessions(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(n_expired_sessions, 1);
+    let n_sessions = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM user_sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_sessions, 0);
+}
+
+#[tokio::test]
+async fn the_username_is_escaped_on_the_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut user = TestUser::generate();
+    user.username = "<b>ursula</b>".into();
+    user.store(&app.db_pool).await;
+
+    // Act
+    user.login(&app).await;
+
+    // Assert
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("Welcome &lt;b&gt;ursula&lt;/b&gt;!"));
+}
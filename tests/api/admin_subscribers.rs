@@ -0,0 +1,469 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+async fn insert_subscriber(app: &TestApp, email: &str, name: &str, status: &str) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES ($1, $2, $3, now(), $4, $5)
+        "#,
+        subscriber_id,
+        email,
+        name,
+        status,
+        subscriber_id.to_string()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+async fn insert_token(app: &TestApp, subscriber_id: Uuid, token: &str) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions_tokens (subscription_token, subscriber_id, created_at, expires_at)
+        VALUES ($1, $2, now(), now() + interval '1 day')
+        "#,
+        token,
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn get_status(app: &TestApp, subscriber_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+
+    // Act
+    let list = app.get_admin_subscribers(&[]).await;
+    let delete = app
+        .post_admin_subscriber_action(subscriber_id, "delete")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&list, "/login");
+    assert_is_redirect_to(&delete, "/login");
+    assert_eq!(get_status(&app, subscriber_id).await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+    insert_subscriber(
+        &app,
+        "octavia@example.com",
+        "Octavia",
+        "pending_confirmation",
+    )
+    .await;
+    insert_subscriber(&app, "ursa@example.com", "Ursa", "unsubscribed").await;
+
+    // Act - Part 1 - No filter
+    let html_page = app.get_admin_subscribers_html(&[]).await;
+    assert!(html_page.contains("<p>3 subscriber(s)</p>"));
+
+    // Act - Part 2 - Search
+    let html_page = app.get_admin_subscribers_html(&[("search", "URS")]).await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("ursa@example.com"));
+    assert!(!html_page.contains("octavia@example.com"));
+
+    // Act - Part 3 - Search and status
+    let html_page = app
+        .get_admin_subscribers_html(&[("search", "urs"), ("status", "confirmed")])
+        .await;
+    assert!(html_page.contains("<p>1 subscriber(s)</p>"));
+    assert!(html_page.contains("ursula@example.com"));
+}
+
+#[tokio::test]
+async fn search_wildcards_are_matched_literally() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+
+    // Act
+    let html_page = app.get_admin_subscribers_html(&[("search", "%")]).await;
+
+    // Assert
+    assert!(html_page.contains("<p>0 subscriber(s)</p>"));
+}
+
+#[tokio::test]
+async fn unknown_statuses_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .get_admin_subscribers(&[("status", "not_a_status")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for i in 0..51 {
+        insert_subscriber(
+            &app,
+            &format!("subscriber{i}@example.com"),
+            "Subscriber",
+            "confirmed",
+        )
+        .await;
+    }
+
+    // Act
+    let first_page = app.get_admin_subscribers_html(&[]).await;
+    let second_page = app.get_admin_subscribers_html(&[("page", "2")]).await;
+
+    // Assert
+    assert!(first_page.contains("Page 1 of 2"));
+    assert_eq!(first_page.matches("@example.com</td>").count(), 50);
+    assert!(second_page.contains("Page 2 of 2"));
+    assert_eq!(second_page.matches("@example.com</td>").count(), 1);
+}
+
+#[tokio::test]
+async fn subscriber_details_are_escaped() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "ursula@example.com", "<b>Ursula</b>", "confirmed").await;
+
+    // Act
+    let html_page = app.get_admin_subscribers_html(&[]).await;
+
+    // Assert
+    assert!(html_page.contains("&lt;b&gt;Ursula&lt;/b&gt;"));
+}
+
+#[tokio::test]
+async fn admins_can_confirm_a_pending_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id =
+        insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation").await;
+    insert_token(&app, subscriber_id, "token").await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "confirm")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_subscribers_html(&[]).await;
+    assert!(html_page.contains("<p><i>ursula@example.com has been confirmed.</i></p>"));
+    assert_eq!(get_status(&app, subscriber_id).await, "confirmed");
+    let n_tokens = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn admins_can_resend_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id =
+        insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation").await;
+    insert_token(&app, subscriber_id, "old-token").await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "resend_confirmation")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let outbox = sqlx::query!("SELECT recipient, text_content FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.recipient, "ursula@example.com");
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscriptions_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_ne!(tokens[0].subscription_token, "old-token");
+    assert!(outbox.text_content.contains(&tokens[0].subscription_token));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_sent_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+
+    // Act
+    app.post_admin_subscriber_action(subscriber_id, "resend_confirmation")
+        .await;
+
+    // Assert
+    let html_page = app.get_admin_subscribers_html(&[]).await;
+    assert!(html_page
+        .contains("<p><i>Only pending subscribers can be sent a confirmation email.</i></p>"));
+    let n_emails = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_emails, 0);
+}
+
+#[tokio::test]
+async fn admins_can_unsubscribe_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    assert_eq!(get_status(&app, subscriber_id).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn admins_can_delete_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id =
+        insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation").await;
+    insert_token(&app, subscriber_id, "token").await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "delete")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_subscribers_html(&[]).await;
+    assert!(html_page.contains("<p><i>ursula@example.com has been deleted.</i></p>"));
+    assert!(html_page.contains("<p>0 subscriber(s)</p>"));
+    let erasure = sqlx::query!("SELECT requested_by, n_tokens FROM subscriber_erasures")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(erasure.requested_by, "admin");
+    assert_eq!(erasure.n_tokens, 1);
+}
+
+#[tokio::test]
+async fn actions_on_unknown_subscribers_are_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(Uuid::new_v4(), "delete")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_subscribers_html(&[]).await;
+    assert!(html_page.contains("<p><i>There is no subscriber matching your request.</i></p>"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_or_export_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let import = app
+        .post_import_subscribers("email,name\nursula@example.com,Ursula\n", "confirmed")
+        .await;
+    let export = app.get_export_subscribers().await;
+
+    // Assert
+    assert_is_redirect_to(&import, "/login");
+    assert_is_redirect_to(&export, "/login");
+}
+
+#[tokio::test]
+async fn imported_subscribers_can_be_marked_as_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "octavia@example.com", "Octavia", "unsubscribed").await;
+    let csv = "email,name\nursula@example.com,Ursula\noctavia@example.com,Octavia\n";
+
+    // Act
+    let response = app.post_import_subscribers(csv, "confirmed").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_subscribers_html(&[]).await;
+    assert!(html_page
+        .contains("<p><i>1 subscriber(s) imported, 1 already subscribed and skipped.</i></p>"));
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved[0].email, "octavia@example.com");
+    assert_eq!(saved[0].status, "unsubscribed");
+    assert_eq!(saved[1].email, "ursula@example.com");
+    assert_eq!(saved[1].status, "confirmed");
+    let n_emails = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_emails, 0);
+}
+
+#[tokio::test]
+async fn imported_subscribers_can_be_sent_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\nursula@example.com,Ursula\noctavia@example.com,Octavia\n";
+
+    // Act
+    app.post_import_subscribers(csv, "send_confirmation").await;
+
+    // Assert
+    let statuses = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert!(statuses.iter().all(|s| s.status == "pending_confirmation"));
+    let recipients = sqlx::query!("SELECT recipient FROM email_outbox ORDER BY recipient")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(recipients[0].recipient, "octavia@example.com");
+    assert_eq!(recipients[1].recipient, "ursula@example.com");
+}
+
+#[tokio::test]
+async fn imports_with_invalid_lines_are_rejected_as_a_whole() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\nursula@example.com,Ursula\nnot-an-email,Octavia\n";
+
+    // Act
+    let response = app.post_import_subscribers(csv, "confirmed").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let html_page = app.get_import_subscribers_html().await;
+    assert!(html_page.contains("<p><i>Line 3: not-an-email is not a valid subscriber email."));
+    let n_subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(
+        &app,
+        "ursula@example.com",
+        "Ursula, the writer",
+        "confirmed",
+    )
+    .await;
+
+    // Act
+    let response = app.get_export_subscribers().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    let mut lines = body.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with(r#"ursula@example.com,"Ursula, the writer",confirmed,"#));
+    assert_eq!(lines.next(), None);
+}
+
+#[tokio::test]
+async fn exported_cells_that_look_like_formulas_are_neutralised() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    insert_subscriber(&app, "=cmd@example.com", "@SUM(A1)", "confirmed").await;
+
+    // Act
+    let response = app.get_export_subscribers().await;
+
+    // Assert
+    let body = response.text().await.unwrap();
+    assert!(body
+        .lines()
+        .nth(1)
+        .unwrap()
+        .starts_with("'=cmd@example.com,'@SUM(A1),confirmed,"));
+}
+
+#[tokio::test]
+async fn viewers_cannot_export_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    // Act
+    let response = app.get_export_subscribers().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
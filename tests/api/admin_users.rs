@@ -0,0 +1,463 @@
+use reqwest::Url;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::subscription_cleanup_worker::delete_expired_user_invites;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+/// Replaces the logged-in owner with a freshly stored user of the given role.
+async fn log_in_as(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::generate_with_role(role);
+    user.store(&app.db_pool).await;
+    app.post_logout().await;
+    user.login(app).await;
+    user
+}
+
+/// Invites `email` as an owner and returns the link from the invite email.
+async fn invite(app: &TestApp, email: &str, role: &str) -> Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_admin_users("/invite", &[("email", email), ("role", role)])
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+async fn accept(
+    link: Url,
+    username: &str,
+    password: &str,
+    password_check: &str,
+) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(link)
+        .form(&[
+            ("username", username),
+            ("password", password),
+            ("password_check", password_check),
+        ])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_newsletter_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    log_in_as(&app, "viewer").await;
+
+    // Act - Part 1 - Open the form
+    let response = app.get_publish_newsletter().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page
+        .contains("<p><i>Your role (viewer) does not allow you to access this page.</i></p>"));
+    assert!(!html_page.contains(r#"href="/admin/newsletters""#));
+
+    // Act - Part 2 - Submit it anyway
+    let response = app
+        .post_publish_newsletter_form(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let n_issues = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_through_the_api() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_can_publish_but_cannot_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+    log_in_as(&app, "editor").await;
+
+    // Act - Part 1 - Publishing
+    let response = app.get_publish_newsletter().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Managing users
+    let response = app.get_admin_users().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app
+        .post_admin_users(
+            "/invite",
+            &[("email", "ursula@example.com"), ("role", "owner")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let n_invites = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM user_invites")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_invites, 0);
+}
+
+#[tokio::test]
+async fn invited_users_create_their_account_with_the_invited_role() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Invite
+    let link = invite(&app, "ursula@example.com", "editor").await;
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("An invite has been sent to ursula@example.com, as editor."));
+    assert!(html_page.contains("<td>ursula@example.com</td>"));
+
+    // Act - Part 2 - Follow the link
+    let page = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+    assert!(page
+        .text()
+        .await
+        .unwrap()
+        .contains("You have been invited as editor"));
+
+    // Act - Part 3 - Create the account
+    let password = uuid::Uuid::new_v4().to_string();
+    let response = accept(link.clone(), "ursula", &password, &password).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 4 - Log in
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "ursula",
+            "password": &password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("Welcome ursula! Your role: editor."));
+    let user = sqlx::query!("SELECT email, role FROM users WHERE username = 'ursula'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(user.email.as_deref(), Some("ursula@example.com"));
+    assert_eq!(user.role, "editor");
+    // Invites are single use.
+    let response = accept(link, "ursula2", &password, &password).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn mistakes_in_the_account_form_keep_the_invite_valid() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let link = invite(&app, "ursula@example.com", "viewer").await;
+    let form_url = format!("{}?{}", link.path(), link.query().unwrap());
+
+    // Act
+    let response = accept(
+        link.clone(),
+        "ursula",
+        "a-long-enough-password",
+        "a-different-password",
+    )
+    .await;
+    assert_is_redirect_to(&response, &form_url);
+    let response = accept(
+        link.clone(),
+        &app.test_user.username,
+        "a-long-enough-password",
+        "a-long-enough-password",
+    )
+    .await;
+    assert_is_redirect_to(&response, &form_url);
+
+    // Assert
+    let n_users =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE email IS NOT NULL")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_users, 0);
+    let page = reqwest::get(link).await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_email_claimed_by_another_account_is_reported_on_the_account_form() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let link = invite(&app, "ursula@example.com", "viewer").await;
+    let form_url = format!("{}?{}", link.path(), link.query().unwrap());
+    sqlx::query!(
+        "UPDATE users SET email = 'ursula@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let password = uuid::Uuid::new_v4().to_string();
+    let response = accept(link.clone(), "ursula", &password, &password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, &form_url);
+    let n_users =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = 'ursula'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_users, 0);
+    let page = reqwest::get(link).await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn usernames_with_markup_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let link = invite(&app, "ursula@example.com", "editor").await;
+    let form_url = format!("{}?{}", link.path(), link.query().unwrap());
+
+    // Act
+    let password = uuid::Uuid::new_v4().to_string();
+    let response = accept(
+        link.clone(),
+        "<script>alert('ursula')</script>",
+        &password,
+        &password,
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &form_url);
+    let n_users =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE email IS NOT NULL")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_users, 0);
+}
+
+#[tokio::test]
+async fn expired_invites_are_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let link = invite(&app, "ursula@example.com", "editor").await;
+    sqlx::query!("UPDATE user_invites SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let password = uuid::Uuid::new_v4().to_string();
+    let response = accept(link, "ursula", &password, &password).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn cleanup_removes_expired_invites() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    invite(&app, "ursula@example.com", "editor").await;
+    assert_eq!(delete_expired_user_invites(&app.db_pool).await.unwrap(), 0);
+    sqlx::query!("UPDATE user_invites SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let n_expired_invites = delete_expired_user_invites(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(n_expired_invites, 1);
+    let n_invites = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "n!" FROM user_invites"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_invites, 0);
+}
+
+#[tokio::test]
+async fn the_email_of_an_existing_user_cannot_be_invited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let link = invite(&app, "ursula@example.com", "editor").await;
+    let password = uuid::Uuid::new_v4().to_string();
+    accept(link, "ursula", &password, &password).await;
+
+    // Act
+    let response = app
+        .post_admin_users(
+            "/invite",
+            &[("email", "ursula@example.com"), ("role", "owner")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("<p><i>ursula@example.com already has an account.</i></p>"));
+}
+
+#[tokio::test]
+async fn owners_can_change_the_role_of_other_users_but_not_their_own() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Promote the viewer
+    let response = app
+        .post_admin_users(&format!("/{}/role", viewer.user_id), &[("role", "editor")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains(&format!("{} is now editor.", viewer.username)));
+
+    // Act - Part 2 - Demote ourselves
+    let response = app
+        .post_admin_users(
+            &format!("/{}/role", app.test_user.user_id),
+            &[("role", "viewer")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("You cannot change your own role."));
+
+    // Assert
+    let role_of = |user_id| {
+        sqlx::query_scalar::<_, String>("SELECT role FROM users WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&app.db_pool)
+    };
+    assert_eq!(role_of(viewer.user_id).await.unwrap(), "editor");
+    assert_eq!(role_of(app.test_user.user_id).await.unwrap(), "owner");
+}
+
+#[tokio::test]
+async fn removed_users_are_logged_out_and_cannot_log_back_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    let editor_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let login_body = serde_json::json!({
+        "username": &editor.username,
+        "password": &editor.password
+    });
+    let response = editor_client
+        .post(format!("{}/login", &app.address))
+        .form(&login_body)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_admin_users(&format!("/{}/delete", editor.user_id), &())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains(&format!("{} has been removed.", editor.username)));
+    let response = editor_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let response = editor_client
+        .post(format!("{}/login", &app.address))
+        .form(&login_body)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn owners_cannot_remove_themselves() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_admin_users(&format!("/{}/delete", app.test_user.user_id), &())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("You cannot remove yourself."));
+}
@@ -1,6 +1,6 @@
 use uuid::Uuid;
 
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, build_api_client, spawn_app};
 
 #[tokio::test]
 async fn you_must_be_logged_in_to_see_the_changed_password_form() {
@@ -24,7 +24,8 @@ async fn you_must_be_logged_in_to_change_your_password() {
     let response = app
         .post_change_password(&serde_json::json!({
             "current_password": Uuid::new_v4().to_string(),
-            "new_password": &new_password
+            "new_password": &new_password,
+            "new_password_check": &new_password,
         }))
         .await;
 
@@ -63,3 +64,228 @@ async fn new_password_fields_must_match() {
          the field values must match.</i></p>"
     ));
 }
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+    let wrong_password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Login
+    app.test_user.login(&app).await;
+
+    // Act - Part 2 - Try to change password
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &wrong_password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn guesses_of_the_current_password_are_throttled() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+    app.test_user.login(&app).await;
+    for _ in 0..3 {
+        app.post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    }
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("Too many failed login attempts. Please wait"));
+    let n_failures =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM login_attempts WHERE throttle_key = $1")
+            .bind(format!("username:{}", app.test_user.username))
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_failures, 3);
+}
+
+#[tokio::test]
+async fn new_password_must_respect_the_length_bounds() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "a".repeat(11),
+            "The new password must be at least 12 characters long.",
+        ),
+        (
+            "a".repeat(129),
+            "The new password must be at most 128 characters long.",
+        ),
+    ];
+
+    // Act - Part 1 - Login
+    app.test_user.login(&app).await;
+
+    for (new_password, error_message) in test_cases {
+        // Act - Part 2 - Try to change password
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }))
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/password");
+
+        // Act - Part 3 - Follow the redirect
+        let html_page = app.get_change_password_html().await;
+        assert!(html_page.contains(&format!("<p><i>{}</i></p>", error_message)));
+    }
+}
+
+#[tokio::test]
+async fn new_password_must_be_different_from_the_current_one() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    app.test_user.login(&app).await;
+
+    // Act - Part 2 - Try to change password
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &app.test_user.password,
+            "new_password_check": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page
+        .contains("<p><i>The new password must be different from the current one.</i></p>"));
+}
+
+#[tokio::test]
+async fn breached_passwords_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    app.test_user.login(&app).await;
+
+    // Act - Part 2 - Try to change password
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": "Password1234",
+            "new_password_check": "Password1234",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains(
+        "<p><i>The new password has appeared in a data breach, please choose another one.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn changing_password_works() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Login
+    app.test_user.login(&app).await;
+
+    // Act - Part 2 - Change password
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    // Act - Part 4 - Login using the new password
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_the_other_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+    let other_client = build_api_client();
+    other_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = other_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/login");
+}
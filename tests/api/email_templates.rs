@@ -0,0 +1,68 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_preview_email_templates() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_email_template_preview("confirmation", "html").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn email_templates_are_rendered_against_a_sample_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for format in ["html", "text"] {
+        // Act
+        let response = app.get_email_template_preview("confirmation", format).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let body = response.text().await.unwrap();
+        assert!(body.contains("Hi Ursula Le Guin,"));
+        assert!(body.contains("/subscriptions/confirm?subscription_token="));
+    }
+}
+
+#[tokio::test]
+async fn newsletter_issue_previews_include_the_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .get_email_template_preview("newsletter_issue", "text")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/plain; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Unsubscribe from this newsletter: "));
+    assert!(body.contains("/subscriptions/unsubscribe?unsubscribe_token="));
+}
+
+#[tokio::test]
+async fn unknown_email_templates_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .get_email_template_preview("not_a_template", "html")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
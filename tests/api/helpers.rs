@@ -1,12 +1,16 @@
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use reqwest::Url;
-use serde::Serialize;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{MockServer, Request, Respond, ResponseTemplate};
 use zero2prod::{
     configuration::{get_configuration, DatabaseSettings},
+    email_client::EmailSender,
+    email_outbox::try_dispatch_email,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    session_store::SessionStoreKind,
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -34,6 +38,7 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: Arc<dyn EmailSender>,
 }
 
 pub struct ConfirmationLinks {
@@ -42,6 +47,27 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_dispatch_email(&self.db_pool, self.email_client.as_ref())
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, self.email_client.as_ref(), &self.address)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
         self.api_client
             .post(format!("{}/subscriptions", &self.address))
@@ -57,7 +83,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .form(body)
             .send()
@@ -67,7 +93,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -76,6 +102,52 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn post_login_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/two_factor", &self.address))
+            .form(&[("code", code)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_settings_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/two_factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Posts a form to one of the two-factor settings actions, e.g. `/enrol`.
+    pub async fn post_two_factor_settings<Body>(
+        &self,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/two_factor{}", &self.address, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_forgot_password(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/forgot", &self.address))
+            .form(&[("username", username)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
             .get(format!("{}/admin/dashboard", &self.address))
@@ -84,10 +156,167 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_email_template_preview(
+        &self,
+        template: &str,
+        format: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/email_templates/{}?format={}",
+                &self.address, template, format
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_admin_dashboard_html(&self) -> String {
         self.get_admin_dashboard().await.text().await.unwrap()
     }
 
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout_other_sessions(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout/others", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_failed_deliveries_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/deliveries/failed", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_requeue_failed_delivery<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/deliveries/failed/requeue", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers", &self.address))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers_html(&self, query: &[(&str, &str)]) -> String {
+        self.get_admin_subscribers(query)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_import_subscribers(&self, csv: &str, mode: &str) -> reqwest::Response {
+        let form = reqwest::multipart::Form::new()
+            .part(
+                "file",
+                reqwest::multipart::Part::text(csv.to_owned())
+                    .file_name("subscribers.csv")
+                    .mime_str("text/csv")
+                    .unwrap(),
+            )
+            .text("mode", mode.to_owned());
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_import_subscribers_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/import", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_export_subscribers(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/export", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_users_html(&self) -> String {
+        self.get_admin_users().await.text().await.unwrap()
+    }
+
+    /// Posts a form to one of the user management actions, e.g. `/invite`.
+    pub async fn post_admin_users<Body>(&self, action: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/users{}", &self.address, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_data_request(&self, email: &str, request: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/privacy", &self.address))
+            .form(&[("email", email), ("request", request)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
             .get(format!("{}/admin/password", &self.address))
@@ -105,7 +334,31 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter_form<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
             .form(body)
             .send()
             .await
@@ -127,20 +380,36 @@ impl TestApp {
             confirmation_link.set_port(Some(self.port)).unwrap();
             confirmation_link
         };
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
 
     pub async fn post_publish_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn post_publish_newsletters_with_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
 }
 
 // Launches our application in the background
@@ -173,6 +442,8 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         // set the `base_url` of the `email_client` to the uri of the mock server.
         c.email_client.base_url = email_server.uri();
+        // keep sessions in-process so the test suite does not need a running Redis
+        c.session_store = SessionStoreKind::Memory;
         // Return the new randomized configuration
         c
     };
@@ -185,23 +456,34 @@ pub async fn spawn_app() -> TestApp {
 
     let application_port = application.port();
 
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(application.run_until_stopped());
 
-    let client = reqwest::Client::builder()
-        .redirect(reqwest::redirect::Policy::none())
-        .cookie_store(true)
-        .build()
-        .unwrap();
+    let client = build_api_client();
     // Don't forget to put the `http` or won't work.
     // return the port in a formatted string that can be used in unit tests.
-    TestApp {
+    let test_app = TestApp {
         address: format!("http:localhost:{}", application_port),
         port: application_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         test_user: TestUser::generate(),
         api_client: client,
-    }
+        email_client: configuration.email_client.client().unwrap(),
+    };
+
+    test_app.test_user.store(&test_app.db_pool).await;
+
+    test_app
+}
+
+// Every client gets its own cookie jar, i.e. its own session.
+pub fn build_api_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
@@ -232,17 +514,23 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 }
 
 pub struct TestUser {
-    user_id: Uuid,
+    pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::generate_with_role("owner")
+    }
+
+    pub fn generate_with_role(role: &'static str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
@@ -254,7 +542,7 @@ impl TestUser {
         .await;
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
 
         let password_hash = Argon2::new(
@@ -267,11 +555,12 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role)
+            VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.role
         )
         .execute(pool)
         .await
@@ -279,6 +568,44 @@ impl TestUser {
     }
 }
 
+/// Answers like Postmark's batch endpoint: every message in the batch is accepted, unless it is
+/// addressed to the recipient passed to `rejecting`.
+#[derive(Default)]
+pub struct PostmarkBatchResponder {
+    delay: std::time::Duration,
+    rejected_recipient: Option<String>,
+}
+
+impl PostmarkBatchResponder {
+    pub fn with_delay(mut self, delay: std::time::Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn rejecting(mut self, recipient: &str) -> Self {
+        self.rejected_recipient = Some(recipient.to_owned());
+        self
+    }
+}
+
+impl Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|message| match &self.rejected_recipient {
+                Some(recipient) if message["To"] == recipient.as_str() => {
+                    serde_json::json!({"ErrorCode": 406, "Message": "Inactive recipient"})
+                }
+                _ => serde_json::json!({"ErrorCode": 0, "Message": "OK"}),
+            })
+            .collect();
+        ResponseTemplate::new(200)
+            .set_body_json(results)
+            .set_delay(self.delay)
+    }
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location)
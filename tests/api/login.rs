@@ -0,0 +1,135 @@
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Try to login
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    // Act - Part 3 - Reload the login page
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("<p><i>Authentication failed</i></p>"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+/// Replaces the password hash of the test user with one computed with weak parameters and an
+/// older algorithm.
+async fn store_outdated_password_hash(app: &TestApp) -> String {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2i,
+        Version::V0x13,
+        Params::new(4096, 1, 1, None).unwrap(),
+    )
+    .hash_password(app.test_user.password.as_bytes(), &salt)
+    .unwrap()
+    .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        password_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    password_hash
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn outdated_password_hashes_are_upgraded_after_a_successful_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let outdated_hash = store_outdated_password_hash(&app).await;
+
+    // Act - Part 1 - Log in
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert - The hash is upgraded in the background
+    let mut upgraded_hash = stored_password_hash(&app).await;
+    for _ in 0..50 {
+        if upgraded_hash != outdated_hash {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        upgraded_hash = stored_password_hash(&app).await;
+    }
+    assert!(upgraded_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+
+    // Act - Part 2 - Log in again, against the new hash
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn outdated_password_hashes_are_kept_after_a_failed_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let outdated_hash = store_outdated_password_hash(&app).await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "wrong-password"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(stored_password_hash(&app).await, outdated_hash);
+}
@@ -0,0 +1,192 @@
+use zero2prod::{configuration::get_configuration, login_throttle::delete_old_login_attempts};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Records `n` failed logins under `throttle_key`, `seconds_ago` seconds ago.
+async fn seed_failures(app: &TestApp, throttle_key: &str, n: i32, seconds_ago: f64) {
+    sqlx::query(
+        r#"
+        INSERT INTO login_attempts (throttle_key, attempted_at)
+        SELECT $1, now() - make_interval(secs => $3) FROM generate_series(1, $2)
+        "#,
+    )
+    .bind(throttle_key)
+    .bind(n)
+    .bind(seconds_ago)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn count_failures(app: &TestApp, throttle_key: &str) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM login_attempts WHERE throttle_key = $1")
+        .bind(throttle_key)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn log_in(app: &TestApp, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": password
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn repeated_failures_delay_the_next_attempt() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        let response = log_in(&app, "wrong-password").await;
+        assert_is_redirect_to(&response, "/login");
+    }
+    app.get_login_html().await;
+
+    // Act
+    let response = log_in(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many failed login attempts. Please wait"));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn locked_out_usernames_cannot_log_in_with_the_right_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let username_key = format!("username:{}", app.test_user.username);
+    seed_failures(&app, &username_key, 10, 0.).await;
+
+    // Act
+    let response = log_in(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(
+        "<p><i>Too many failed login attempts. Logins are locked for 15 minute(s).</i></p>"
+    ));
+    // Throttled attempts are not recorded, they would extend the lockout.
+    assert_eq!(count_failures(&app, &username_key).await, 10);
+}
+
+#[tokio::test]
+async fn failures_outside_the_window_are_forgotten() {
+    // Arrange
+    let app = spawn_app().await;
+    let username_key = format!("username:{}", app.test_user.username);
+    seed_failures(&app, &username_key, 10, 16. * 60.).await;
+
+    // Act
+    let response = log_in(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn failures_outside_the_window_are_pruned() {
+    // Arrange
+    let app = spawn_app().await;
+    seed_failures(&app, "username:old", 2, 16. * 60.).await;
+    seed_failures(&app, "username:recent", 1, 60.).await;
+    let settings = get_configuration().unwrap().login_throttle;
+
+    // Act
+    let n_old_login_attempts = delete_old_login_attempts(&app.db_pool, &settings)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(n_old_login_attempts, 2);
+    assert_eq!(count_failures(&app, "username:old").await, 0);
+    assert_eq!(count_failures(&app, "username:recent").await, 1);
+}
+
+#[tokio::test]
+async fn too_many_failures_from_one_address_lock_out_every_username() {
+    // Arrange
+    let app = spawn_app().await;
+    seed_failures(&app, "ip:127.0.0.1", 100, 0.).await;
+
+    // Act
+    let response = log_in(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Logins are locked"));
+}
+
+#[tokio::test]
+async fn a_successful_login_only_forgets_the_failures_of_the_username() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..2 {
+        log_in(&app, "wrong-password").await;
+    }
+
+    // Act
+    let response = log_in(&app, &app.test_user.password).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let username_key = format!("username:{}", app.test_user.username);
+    assert_eq!(count_failures(&app, &username_key).await, 0);
+    assert_eq!(count_failures(&app, "ip:127.0.0.1").await, 2);
+}
+
+#[tokio::test]
+async fn basic_credentials_are_throttled_with_a_429() {
+    // Arrange
+    let app = spawn_app().await;
+    let username_key = format!("username:{}", app.test_user.username);
+    seed_failures(&app, &username_key, 10, 0.).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: i64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 14 * 60 && retry_after <= 15 * 60);
+}
+
+#[tokio::test]
+async fn forwarding_headers_from_untrusted_clients_are_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Forwarded-For", "203.0.113.7")
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "wrong-password"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(count_failures(&app, "ip:203.0.113.7").await, 0);
+    assert_eq!(count_failures(&app, "ip:127.0.0.1").await, 1);
+}
@@ -1,6 +1,16 @@
+mod admin_dashboard;
+mod admin_subscribers;
+mod admin_users;
 mod change_password;
+mod email_templates;
 mod health_check;
 mod helpers;
+mod login;
+mod login_throttle;
 mod newsletter;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_privacy;
+mod subscriptions_unsubscribe;
+mod two_factor;
@@ -1,4 +1,8 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use zero2prod::idempotency::delete_expired_idempotency_keys;
+
+use crate::helpers::{
+    assert_is_redirect_to, spawn_app, ConfirmationLinks, PostmarkBatchResponder, TestApp,
+};
 use wiremock::{
     matchers::{any, method, path},
     Mock, ResponseTemplate,
@@ -19,6 +23,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .await
         .error_for_status()
         .unwrap();
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app
         .email_server
@@ -28,7 +33,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
 async fn create_confirmed_subscriber(app: &TestApp) {
@@ -65,6 +70,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     // Assert
     assert_eq!(response.status().as_u16(), reqwest::StatusCode::OK);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -73,9 +79,9 @@ pub async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method(reqwest::Method::POST))
-        .respond_with(ResponseTemplate::new(reqwest::StatusCode::OK))
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -91,7 +97,8 @@ pub async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let response = app.post_publish_newsletters(newletter_request_body).await;
 
     // Assert
-    assert_eq!(response.status().as_u16(), reqwest::StatusCode::OK)
+    assert_eq!(response.status().as_u16(), reqwest::StatusCode::OK);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -133,3 +140,938 @@ async fn newsletters_returns_400_for_invalid_data() {
         );
     }
 }
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit newsletter
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content" : {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_publish_newsletters_with_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Submit newsletter again
+    let response = app
+        .post_publish_newsletters_with_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_submission_is_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        // Setting a long delay to ensure that the second request arrives before the first one
+        // completes
+        .respond_with(
+            PostmarkBatchResponder::default().with_delay(std::time::Duration::from_secs(2)),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Submit two newsletters concurrently
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content" : {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response1 =
+        app.post_publish_newsletters_with_key(&newsletter_request_body, &idempotency_key);
+    let response2 =
+        app.post_publish_newsletters_with_key(&newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_can_be_reused_and_are_pruned() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_request_body = newsletter_request_body();
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_publish_newsletters_with_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Reuse the key
+    let response = app
+        .post_publish_newsletters_with_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let n_issues = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 2);
+
+    // Act - Part 2 - Prune
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let n_expired_keys = delete_expired_idempotency_keys(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(n_expired_keys, 1);
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content" : {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+
+    // Act
+    let response = app
+        .post_publish_newsletters_with_key(&newsletter_request_body, &"a".repeat(50))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn publishing_does_not_wait_for_the_email_provider() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content" : {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    });
+    let response = app.post_publish_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let n_queued_tasks = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued_tasks, 1);
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter Title",
+        "content" : {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    })
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"is_delayed!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The delivery task should still be queued.");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.is_delayed);
+}
+
+#[tokio::test]
+async fn permanent_delivery_failures_are_dead_lettered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    let dead_letter =
+        sqlx::query!("SELECT subscriber_email, n_attempts FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("The delivery should have been dead-lettered.");
+    assert_eq!(dead_letter.subscriber_email, "space_daddy@test.com");
+    assert_eq!(dead_letter.n_attempts, 1);
+}
+
+#[tokio::test]
+async fn subscribers_erased_while_their_delivery_fails_are_not_dead_lettered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(ResponseTemplate::new(422).set_delay(std::time::Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.test_user.login(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act - The subscriber is erased while the provider is rejecting their email
+    let erase = async {
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        app.post_admin_subscriber_action(subscriber_id, "delete")
+            .await
+    };
+    let (_, response) = tokio::join!(app.dispatch_all_pending_emails(), erase);
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let n_dead_letters =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_dead_letters"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_dead_letters, 0);
+}
+
+#[tokio::test]
+async fn admins_can_inspect_and_requeue_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let permanent_failure = Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    drop(permanent_failure);
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Inspect the failed deliveries
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(html_page.contains("space_daddy@test.com"));
+
+    // Act - Part 2 - Requeue the delivery
+    let response = app
+        .post_requeue_failed_delivery(&serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "subscriber_email": "space_daddy@test.com",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/deliveries/failed");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(
+        html_page.contains("<p><i>The delivery to space_daddy@test.com has been requeued.</i></p>")
+    );
+
+    // Act - Part 4 - Deliver the requeued email
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_escaped_in_the_admin_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, text_content, html_content, published_at)
+        VALUES ($1, '<script>alert("title")</script>', 'text', '<p>html</p>', now())
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters
+            (newsletter_issue_id, subscriber_email, n_attempts, last_error, failed_at)
+        VALUES ($1, 'a"><script>@example.com', 1, '<b>error</b>', now())
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_failed_deliveries_html().await;
+
+    // Assert
+    assert!(!html_page.contains("<script>"));
+    assert!(!html_page.contains("<b>error</b>"));
+    assert!(html_page.contains("&lt;script&gt;alert(&quot;title&quot;)&lt;/script&gt;"));
+    assert!(html_page.contains(r#"value="a&quot;&gt;&lt;script&gt;@example.com""#));
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    // Random credentials
+    let username = uuid::Uuid::new_v4().to_string();
+    let password = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    // Random password
+    let password = uuid::Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn logged_in_admins_can_publish_without_basic_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_publish_newsletter().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter_from_the_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter_form(&newsletter_form_body())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_newsletter_form_publishes_an_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Load the form
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(r#"name="idempotency_key""#));
+
+    // Act - Part 2 - Submit the form
+    let newsletter_form_body = newsletter_form_body();
+    let response = app
+        .post_publish_newsletter_form(&newsletter_form_body)
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    // Act - Part 4 - Submit the same form again
+    let response = app
+        .post_publish_newsletter_form(&newsletter_form_body)
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 5 - Follow the redirect
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[tokio::test]
+async fn the_newsletter_form_rejects_incomplete_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "title": "",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": uuid::Uuid::new_v4().to_string()
+            }),
+            "The newsletter issue must have a title.",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "text_content": " ",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": uuid::Uuid::new_v4().to_string()
+            }),
+            "The plain text content of the issue cannot be empty.",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "",
+                "idempotency_key": uuid::Uuid::new_v4().to_string()
+            }),
+            "The HTML content of the issue cannot be empty.",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "text_content": "Hi {{ first_name }}",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": uuid::Uuid::new_v4().to_string()
+            }),
+            "`{{ first_name }}` is not a known merge field.",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "text_content": "Hi {{ <img> }}",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": uuid::Uuid::new_v4().to_string()
+            }),
+            "`{{ &lt;img&gt; }}` is not a known merge field.",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // Act - Part 1 - Submit the form
+        let response = app.post_publish_newsletter_form(&invalid_body).await;
+        assert_is_redirect_to(&response, "/admin/newsletters");
+
+        // Act - Part 2 - Follow the redirect
+        let html_page = app.get_publish_newsletter_html().await;
+        assert!(html_page.contains(&format!("<p><i>{error_message}")));
+    }
+
+    app.dispatch_all_pending_emails().await;
+}
+
+fn newsletter_form_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })
+}
+
+#[tokio::test]
+async fn emails_rejected_within_a_batch_are_dead_lettered_individually() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES ($1, 'inactive@test.com', 'inactive', now(), 'confirmed', 'inactive-token')
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default().rejecting("inactive@test.com"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    let dead_letters = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].subscriber_email, "inactive@test.com");
+}
+
+#[tokio::test]
+async fn a_task_that_cannot_be_prepared_does_not_hold_back_the_rest_of_the_batch() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES ($1, 'not-an-email', 'broken', now(), 'confirmed', 'broken-token')
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    let dead_letters = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].subscriber_email, "not-an-email");
+}
+
+#[tokio::test]
+async fn newsletters_carry_a_working_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let delivery = Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = delivery.received_requests().await.pop().unwrap();
+    drop(delivery);
+
+    // Act - Part 1 - Inspect the delivered email
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let email = &body[0];
+    let unsubscribe_header = email["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|header| header["Name"] == "List-Unsubscribe")
+        .expect("The email has no List-Unsubscribe header.");
+    let unsubscribe_url = unsubscribe_header["Value"]
+        .as_str()
+        .unwrap()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_owned();
+    assert!(unsubscribe_url.starts_with(&format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token=",
+        app.address
+    )));
+    assert!(email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&unsubscribe_url));
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&unsubscribe_url));
+
+    // Act - Part 2 - Unsubscribe
+    reqwest::Client::new()
+        .post(&unsubscribe_url)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act - Part 3 - Publish another issue
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_with_unknown_merge_fields_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let test_cases = vec![
+        (
+            "Hi {{ first_name }}",
+            "an unknown merge field",
+            "`{{ first_name }}` is not a known merge field.",
+        ),
+        (
+            "Hi {{ name",
+            "an unterminated merge field",
+            "A merge field is missing its closing `}}`.",
+        ),
+    ];
+
+    for (text_content, description, error_message) in test_cases {
+        let body = serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": text_content,
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        });
+
+        // Act
+        let response = app.post_publish_newsletters(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+        assert!(response.text().await.unwrap().starts_with(error_message));
+    }
+    let n_issues = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn merge_fields_are_filled_in_for_every_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let delivery = Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ name }}, you subscribed on {{ subscribed_at }}.",
+            "html": "<p>Hi {{name}}!</p><a href=\"{{ unsubscribe_url }}\">Leave</a>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = delivery.received_requests().await.pop().unwrap();
+    let email = &serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap()[0];
+    let subscribed_at = sqlx::query!("SELECT subscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscribed_at;
+    let text_body = email["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with(&format!(
+        "Hi spacedaddy, you subscribed on {}.",
+        subscribed_at.format("%B %-d, %Y")
+    )));
+    let html_body = email["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("<p>Hi spacedaddy!</p>"));
+    assert!(html_body.contains(&format!(
+        "<a href=\"{}/subscriptions/unsubscribe?unsubscribe_token=",
+        app.address
+    )));
+    assert!(!html_body.contains("{{"));
+}
+
+#[tokio::test]
+async fn issues_published_before_merge_fields_are_delivered_as_is() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, text_content, html_content, published_at)
+        VALUES ($1, 'Legacy issue', 'Hi {{ name }} {{ oops', '<p>{{ first_name }}</p>', now())
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email FROM subscriptions
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let delivery = Mock::given(path("/email/batch"))
+        .and(method(reqwest::Method::POST))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = delivery.received_requests().await.pop().unwrap();
+    let email = &serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap()[0];
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi {{ name }} {{ oops"));
+    assert!(email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("<p>{{ first_name }}</p>"));
+}
@@ -0,0 +1,286 @@
+use reqwest::Url;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::subscription_cleanup_worker::delete_expired_password_reset_tokens;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = 'admin@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Requests a password reset for the test user and returns the emailed link, pointed at the
+/// address the API client uses so that flash messages and sessions share the same cookies.
+async fn request_reset_link(app: &TestApp) -> Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_is_redirect_to(&response, "/login");
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = app.get_confirmation_links(&email_request).html;
+    Url::parse(&format!(
+        "{}{}?{}",
+        app.address,
+        link.path(),
+        link.query().unwrap()
+    ))
+    .unwrap()
+}
+
+async fn post_reset(
+    app: &TestApp,
+    link: Url,
+    new_password: &str,
+    new_password_check: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(link)
+        .form(&[
+            ("new_password", new_password),
+            ("new_password_check", new_password_check),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn unknown_usernames_and_users_without_an_email_get_the_same_response() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let message = "If this username has an email address, a link to reset its password has been \
+                   sent to it.";
+
+    for username in ["not-a-user", app.test_user.username.as_str()] {
+        // Act
+        let response = app.post_forgot_password(username).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/login");
+        assert!(app.get_login_html().await.contains(message));
+    }
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn repeated_reset_requests_are_throttled() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    for _ in 0..3 {
+        let response = app.post_forgot_password(&app.test_user.username).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    // Act
+    let response = app.post_forgot_password(&app.test_user.username).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/forgot");
+    let html_page = app
+        .api_client
+        .get(format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Too many password reset requests."));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_reset_changes_the_password_and_ends_every_session() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    app.test_user.login(&app).await;
+    let link = request_reset_link(&app).await;
+
+    // Act - Part 1 - Follow the link
+    let page = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+
+    // Act - Part 2 - Choose a new password
+    let new_password = uuid::Uuid::new_v4().to_string();
+    let response = post_reset(&app, link.clone(), &new_password, &new_password).await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app
+        .get_login_html()
+        .await
+        .contains("Your password has been reset, you can now log in."));
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    // Links are single use.
+    let response = post_reset(&app, link, &new_password, &new_password).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn reset_tokens_are_stored_hashed() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+
+    // Act
+    let link = request_reset_link(&app).await;
+
+    // Assert
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "reset_token")
+        .unwrap()
+        .1
+        .into_owned();
+    let stored = sqlx::query_scalar::<_, String>("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, token);
+    assert_eq!(stored.len(), 64);
+}
+
+#[tokio::test]
+async fn only_the_latest_reset_link_works() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let first_link = request_reset_link(&app).await;
+    let second_link = request_reset_link(&app).await;
+
+    // Act
+    let first = app.api_client.get(first_link).send().await.unwrap();
+    let second = app.api_client.get(second_link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 401);
+    assert_eq!(second.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let new_password = uuid::Uuid::new_v4().to_string();
+    let response = post_reset(&app, link, &new_password, &new_password).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn cleanup_removes_expired_reset_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    request_reset_link(&app).await;
+    assert_eq!(
+        delete_expired_password_reset_tokens(&app.db_pool)
+            .await
+            .unwrap(),
+        0
+    );
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let n_expired_tokens = delete_expired_password_reset_tokens(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(n_expired_tokens, 1);
+    let n_tokens = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "n!" FROM password_reset_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn mismatched_new_passwords_keep_the_link_valid() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    let form_url = format!("{}?{}", link.path(), link.query().unwrap());
+
+    // Act
+    let response = post_reset(
+        &app,
+        link.clone(),
+        "a-long-enough-password",
+        "a-different-password",
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &form_url);
+    let page = app.api_client.get(link).send().await.unwrap();
+    assert!(page.text().await.unwrap().contains(
+        "<p><i>You entered two different new passwords - the field values must match.</i></p>"
+    ));
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
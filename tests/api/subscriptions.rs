@@ -11,13 +11,6 @@ async fn subscribe_returns_a_200_for_valid_data() {
     let test_app = spawn_app().await;
     let body = "name=spacedaddy&email=space_daddy%40test.com";
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(1)
-        .mount(&test_app.email_server)
-        .await;
-
     // Act
     let response = test_app.post_subscription(body.into()).await;
 
@@ -60,6 +53,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     // Act
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
 }
@@ -78,8 +72,9 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     // Act
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Assert
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
@@ -150,3 +145,221 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500)
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_sends_a_new_confirmation_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=spacedaddy&email=space_daddy%40test.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_response = app.post_subscription(body.into()).await;
+    let second_response = app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(first_link, second_link);
+    // Only the latest link is valid
+    let response = reqwest::get(first_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_again_under_a_new_name_updates_the_stored_name() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let response = app
+        .post_subscription("name=ursula&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.name, "ursula");
+}
+
+#[tokio::test]
+async fn concurrent_subscriptions_with_the_same_email_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=spacedaddy&email=space_daddy%40test.com";
+
+    // Act
+    let (first_response, second_response) = tokio::join!(
+        app.post_subscription(body.into()),
+        app.post_subscription(body.into())
+    );
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribing_a_confirmed_email_again_is_a_no_op() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=spacedaddy&email=space_daddy%40test.com";
+
+    let confirmation_email = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    drop(confirmation_email);
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscription(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_can_opt_back_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=spacedaddy&email=space_daddy%40test.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let unsubscribe_token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, unsubscribe_token
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act - Part 1 - Subscribe again
+    let response = app.post_subscription(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    app.dispatch_all_pending_emails().await;
+
+    // Act - Part 2 - Confirm again
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_and_retries_the_confirmation_email_if_the_provider_is_down() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=spacedaddy&email=space_daddy%40test.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    // The email stays in the outbox, to be sent again later.
+    let outbox = sqlx::query!("SELECT n_retries FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.n_retries, 1);
+}
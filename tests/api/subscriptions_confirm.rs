@@ -3,7 +3,9 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::subscription_cleanup_worker::delete_expired_subscriptions;
+
+use crate::helpers::{spawn_app, TestApp};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -32,8 +34,9 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
     let response = reqwest::get(confirmation_links.html).await.unwrap();
@@ -55,8 +58,9 @@ async fn clicking_on_the_confirmation_link_confirms_a_subsciber() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
     reqwest::get(confirmation_links.html)
@@ -75,3 +79,113 @@ async fn clicking_on_the_confirmation_link_confirms_a_subsciber() {
     assert_eq!(saved.email, "space_daddy@example.com");
     assert_eq!(saved.status, "confirmed")
 }
+
+async fn subscribe(app: &TestApp, body: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+#[tokio::test]
+async fn confirmation_links_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_link =
+        subscribe(&app, "name=space%20daddy&email=space_daddy%40example.com").await;
+    reqwest::get(confirmation_link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_link =
+        subscribe(&app, "name=space%20daddy&email=space_daddy%40example.com").await;
+    sqlx::query!("UPDATE subscriptions_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn cleanup_removes_expired_tokens_and_never_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    // Confirmed before its token expired
+    let confirmation_link = subscribe(&app, "name=confirmed&email=confirmed%40example.com").await;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Still within the confirmation window
+    subscribe(&app, "name=pending&email=pending%40example.com").await;
+    // Never confirmed
+    subscribe(&app, "name=expired&email=expired%40example.com").await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions_tokens
+        SET expires_at = now() - interval '1 minute'
+        WHERE subscriber_id = (SELECT id FROM subscriptions WHERE email = 'expired@example.com')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = delete_expired_subscriptions(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(outcome.n_expired_tokens, 1);
+    assert_eq!(outcome.n_unconfirmed_subscribers, 1);
+    let remaining: Vec<_> = sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect();
+    assert_eq!(
+        remaining,
+        vec!["confirmed@example.com", "pending@example.com"]
+    );
+}
@@ -0,0 +1,225 @@
+use reqwest::Url;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::subscription_cleanup_worker::delete_expired_data_requests;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscription("name=space%20daddy&email=space_daddy%40example.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    reqwest::get(app.get_confirmation_links(&email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Makes a data request and returns the link emailed to the subscriber.
+async fn request_data(app: &TestApp, request: &str) -> Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_data_request("space_daddy@example.com", request)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+async fn count(app: &TestApp, table: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn requests_for_unknown_emails_look_the_same_but_send_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_data_request("nobody@example.com", "export").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn requests_with_an_invalid_email_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_data_request("not-an-email", "export").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_can_download_their_data_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = request_data(&app, "export").await;
+
+    // Act - Part 1 - Follow the link
+    let page = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+    assert!(page.text().await.unwrap().contains("Download my data"));
+
+    // Act - Part 2 - Download
+    let response = reqwest::Client::new()
+        .post(link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscription"]["email"], "space_daddy@example.com");
+    assert_eq!(export["subscription"]["status"], "confirmed");
+    assert_eq!(export["data_requests"][0]["kind"], "export");
+
+    // Act - Part 3 - The link cannot be used twice
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_can_erase_their_data() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = request_data(&app, "erasure").await;
+
+    // Act
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(count(&app, "subscriptions").await, 0);
+    assert_eq!(count(&app, "subscriptions_tokens").await, 0);
+    assert_eq!(count(&app, "subscriber_data_requests").await, 0);
+    let erasure =
+        sqlx::query!("SELECT requested_by, subscription_status, n_tokens FROM subscriber_erasures")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(erasure.requested_by, "subscriber");
+    assert_eq!(erasure.subscription_status, "confirmed");
+    assert_eq!(erasure.n_tokens, 0);
+}
+
+#[tokio::test]
+async fn erasure_cancels_pending_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = request_data(&app, "erasure").await;
+    app.post_publish_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    assert_eq!(count(&app, "issue_delivery_queue").await, 1);
+
+    // Act
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(count(&app, "issue_delivery_queue").await, 0);
+    let n_pending_deliveries = sqlx::query!("SELECT n_pending_deliveries FROM subscriber_erasures")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n_pending_deliveries;
+    assert_eq!(n_pending_deliveries, 1);
+}
+
+#[tokio::test]
+async fn expired_data_request_links_are_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = request_data(&app, "erasure").await;
+    sqlx::query!("UPDATE subscriber_data_requests SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert_eq!(count(&app, "subscriptions").await, 1);
+}
+
+#[tokio::test]
+async fn cleanup_removes_expired_data_requests() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    request_data(&app, "export").await;
+    assert_eq!(delete_expired_data_requests(&app.db_pool).await.unwrap(), 0);
+    sqlx::query!("UPDATE subscriber_data_requests SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let n_expired_requests = delete_expired_data_requests(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(n_expired_requests, 1);
+    assert_eq!(count(&app, "subscriber_data_requests").await, 0);
+    assert_eq!(count(&app, "subscriptions").await, 1);
+}
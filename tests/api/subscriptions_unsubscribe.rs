@@ -0,0 +1,113 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_subscriber(app: &TestApp) -> String {
+    let body = "name=space%20daddy&email=space_daddy%40example.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_without_token_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!("{}/subscriptions/unsubscribe", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unknown_unsubscribe_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token=not-a-token",
+        app.address
+    );
+
+    // Act
+    let get_response = reqwest::get(&url).await.unwrap();
+    let post_response = reqwest::Client::new().post(&url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 401);
+    assert_eq!(post_response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn opening_the_unsubscribe_link_asks_for_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    let unsubscribe_token = create_subscriber(&app).await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        app.address, unsubscribe_token
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(&format!(
+        r#"action="/subscriptions/unsubscribe?unsubscribe_token={}""#,
+        unsubscribe_token
+    )));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_requests_unsubscribe_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let unsubscribe_token = create_subscriber(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, unsubscribe_token
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
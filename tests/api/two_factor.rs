@@ -0,0 +1,358 @@
+use zero2prod::authentication::TotpSecret;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// The text of the first `<code>` element following `after` in the page.
+fn extract_code_element<'a>(html: &'a str, after: &str) -> &'a str {
+    let start = html.find(after).unwrap() + after.len();
+    let start = start + html[start..].find("<code>").unwrap() + "<code>".len();
+    let end = start + html[start..].find("</code>").unwrap();
+    &html[start..end]
+}
+
+/// Enables two-factor authentication for the logged-in test user, returning the secret and the
+/// recovery codes.
+async fn enable_two_factor(app: &TestApp) -> (TotpSecret, Vec<String>) {
+    let response = app.post_two_factor_settings("/enrol", &()).await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_two_factor_settings_html().await;
+    let secret = TotpSecret::from_base32(extract_code_element(
+        &html_page,
+        "enter this secret by hand",
+    ))
+    .unwrap();
+
+    let response = app
+        .post_two_factor_settings("/confirm", &[("code", secret.code_at(now()))])
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let recovery_codes = html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|s| s.split("</code>").next().unwrap().to_string())
+        .collect();
+    (secret, recovery_codes)
+}
+
+/// Moves the recorded login attempts back in time, past the delays of the login throttle.
+async fn wait_out_the_login_throttle(app: &TestApp) {
+    sqlx::query!("UPDATE login_attempts SET attempted_at = attempted_at - interval '2 minutes'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn log_in_with_password(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn enrolment_is_only_confirmed_with_a_valid_code() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_two_factor_settings("/enrol", &()).await;
+
+    // Act
+    let response = app
+        .post_two_factor_settings("/confirm", &[("code", "not-a-code")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<p><i>The code is not valid, please try again.</i></p>"));
+    assert!(html_page.contains("Scan this QR code"));
+    app.post_logout().await;
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn secrets_are_stored_encrypted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let (secret, recovery_codes) = enable_two_factor(&app).await;
+
+    // Assert
+    assert_eq!(recovery_codes.len(), 10);
+    let stored = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_secret
+    .unwrap();
+    assert_ne!(stored, secret.as_bytes());
+    assert!(!stored
+        .windows(secret.as_bytes().len())
+        .any(|window| window == secret.as_bytes()));
+}
+
+#[tokio::test]
+async fn the_password_alone_does_not_log_in_when_two_factor_is_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act - Part 1 - Password
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Code from the next time step, the current one was used to enrol
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let code = secret.code_at(now() + 30);
+    let response = app.post_login_two_factor(&code).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Codes cannot be replayed.
+    app.post_logout().await;
+    log_in_with_password(&app).await;
+    let response = app.post_login_two_factor(&code).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act - Part 1 - Use a recovery code
+    log_in_with_password(&app).await;
+    let response = app
+        .post_login_two_factor(&recovery_codes[0].to_uppercase())
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("You logged in with a recovery code, 9 left."));
+
+    // Act - Part 2 - Use it again
+    app.post_logout().await;
+    log_in_with_password(&app).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn too_many_invalid_codes_restart_the_login() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+    log_in_with_password(&app).await;
+
+    // Act
+    for _ in 0..4 {
+        let response = app.post_login_two_factor("not-a-code").await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+        wait_out_the_login_throttle(&app).await;
+    }
+    let response = app.post_login_two_factor("not-a-code").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app
+        .get_login_html()
+        .await
+        .contains("Too many invalid codes, please log in again."));
+    let response = app.post_login_two_factor("not-a-code").await;
+    assert_is_redirect_to(&response, "/login");
+    // Every invalid code counts as a failure for the login throttle.
+    let n_failures =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM login_attempts WHERE throttle_key = $1")
+            .bind(format!("username:{}", app.test_user.username))
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_failures, 5);
+}
+
+#[tokio::test]
+async fn invalid_codes_are_throttled_like_passwords() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    log_in_with_password(&app).await;
+    for _ in 0..3 {
+        app.post_login_two_factor("not-a-code").await;
+    }
+
+    // Act
+    let response = app.post_login_two_factor(&secret.code_at(now() + 30)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let n_failures =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM login_attempts WHERE throttle_key = $1")
+            .bind(format!("username:{}", app.test_user.username))
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_failures, 3);
+}
+
+#[tokio::test]
+async fn logging_in_again_does_not_reset_the_count_of_invalid_codes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+    log_in_with_password(&app).await;
+    for _ in 0..3 {
+        let response = app.post_login_two_factor("not-a-code").await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+        wait_out_the_login_throttle(&app).await;
+    }
+
+    // Act
+    log_in_with_password(&app).await;
+    wait_out_the_login_throttle(&app).await;
+    let response = app.post_login_two_factor("not-a-code").await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    wait_out_the_login_throttle(&app).await;
+    let response = app.post_login_two_factor("not-a-code").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_second_step_requires_the_first_one() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_login_two_factor("123456").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn disabling_two_factor_requires_the_password() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+
+    // Act - Part 1 - Wrong password
+    let response = app
+        .post_two_factor_settings("/disable", &[("current_password", "wrong-password")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    assert!(app
+        .get_two_factor_settings_html()
+        .await
+        .contains("The current password is incorrect."));
+
+    // Act - Part 2 - Right password
+    let response = app
+        .post_two_factor_settings(
+            "/disable",
+            &[("current_password", app.test_user.password.as_str())],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+
+    // Assert
+    app.post_logout().await;
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn disabling_two_factor_is_locked_out_after_too_many_wrong_passwords() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO login_attempts (throttle_key, attempted_at)
+        SELECT $1, now() FROM generate_series(1, 10)
+        "#,
+        format!("username:{}", app.test_user.username)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_two_factor_settings(
+            "/disable",
+            &[("current_password", app.test_user.password.as_str())],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("Logins are locked"));
+    let enabled = sqlx::query!(
+        r#"SELECT totp_secret IS NOT NULL AS "enabled!" FROM users WHERE user_id = $1"#,
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .enabled;
+    assert!(enabled);
+}
+
+#[tokio::test]
+async fn basic_credentials_are_rejected_when_two_factor_is_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act
+    let response = app
+        .post_publish_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}